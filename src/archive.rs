@@ -0,0 +1,283 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use tar::{Archive, Builder};
+use tempfile::TempDir;
+use zip::ZipArchive;
+
+/// tar 归档的底层写入目标，`.tar.gz` / `.tgz` 使用 gzip 压缩
+enum TarWriter {
+    Plain(File),
+    Gzip(Box<GzEncoder<File>>),
+}
+
+impl TarWriter {
+    fn finish(self) -> io::Result<File> {
+        match self {
+            TarWriter::Plain(file) => Ok(file),
+            TarWriter::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for TarWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TarWriter::Plain(file) => file.write(buf),
+            TarWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TarWriter::Plain(file) => file.flush(),
+            TarWriter::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// 将整次运行的输出打包为一个 tar 归档
+/// 先写入同目录下的临时文件，[`TarOutput::finish`] 时再重命名，保证归档要么完整要么不存在
+pub struct TarOutput {
+    // 多本书并行处理，追加时需要加锁
+    builder: Mutex<Builder<TarWriter>>,
+    path: PathBuf,
+    temp_path: PathBuf,
+}
+
+impl TarOutput {
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".partial");
+        let temp_path = path.with_file_name(temp_name);
+
+        let file = File::create(&temp_path)?;
+        let writer = if is_gzip(&path) {
+            TarWriter::Gzip(Box::new(GzEncoder::new(file, Compression::default())))
+        } else {
+            TarWriter::Plain(file)
+        };
+
+        Ok(Self {
+            builder: Mutex::new(Builder::new(writer)),
+            path,
+            temp_path,
+        })
+    }
+
+    /// 在已有的归档上继续追加（监听模式下每转换一本书追加一次）：原有条目先复制到新的临时文件，
+    /// [`TarOutput::finish`] 后归档同时包含原有条目和新加入的条目；归档不存在时与 [`TarOutput::create`] 相同
+    pub fn reopen(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let output = Self::create(&path)?;
+        if path.exists() {
+            let file = File::open(&path)?;
+            let reader: Box<dyn Read> = if is_gzip(&path) {
+                Box::new(GzDecoder::new(file))
+            } else {
+                Box::new(file)
+            };
+            let mut builder = output
+                .builder
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Tar archive lock poisoned"))?;
+            for entry in Archive::new(reader).entries()? {
+                let mut entry = entry?;
+                // 通过 append_data 重新写入路径，超长路径的扩展头不会丢失
+                let entry_path = entry.path()?.into_owned();
+                let mut header = entry.header().clone();
+                builder.append_data(&mut header, entry_path, &mut entry)?;
+            }
+        }
+        Ok(output)
+    }
+
+    /// 将一本书的输出目录以 `name/` 为前缀加入归档，保留目录结构
+    pub fn append_dir(&self, name: &str, dir: &Path) -> Result<()> {
+        let mut builder = self
+            .builder
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Tar archive lock poisoned"))?;
+        builder.append_dir_all(name, dir)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        let builder = self
+            .builder
+            .into_inner()
+            .map_err(|_| anyhow::anyhow!("Tar archive lock poisoned"))?;
+        let file = builder.into_inner()?.finish()?;
+        file.sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+fn is_gzip(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// 从 zip 包中解压出的 EPUB，临时目录在 drop 时删除
+pub struct NestedEpubs {
+    dir: TempDir,
+    /// 解压出的 EPUB 的路径和它在 zip 包内的相对路径
+    pub epubs: Vec<(PathBuf, PathBuf)>,
+}
+
+impl NestedEpubs {
+    /// 处理完成后删除临时目录，删除失败时返回错误
+    pub fn close(self) -> Result<()> {
+        self.dir.close()?;
+        Ok(())
+    }
+}
+
+/// 将 zip 包中的所有 `.epub` 解压到单独的临时目录，保留包内的目录结构，
+/// 不同目录下的同名 EPUB 不会互相覆盖；包内路径重复的条目只保留第一个
+pub fn extract_nested_epubs(zip_path: &Path) -> Result<NestedEpubs> {
+    let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+    let dir = tempfile::tempdir()?;
+    let mut epubs = Vec::new();
+    let mut seen = HashSet::new();
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        // enclosed_name 拒绝 `../` 和绝对路径，解压时不会跳出临时目录
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        if !entry.is_file() || name.extension().is_none_or(|ext| ext != "epub") {
+            continue;
+        }
+        if !seen.insert(name.clone()) {
+            log::warn!("{}: skipped duplicate entry {}", zip_path.display(), name.display());
+            continue;
+        }
+
+        let path = dir.path().join(&name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut entry, &mut File::create(&path)?)?;
+        epubs.push((path, name));
+    }
+    Ok(NestedEpubs { dir, epubs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 归档中所有 `.txt` 条目的路径，已排序
+    fn txt_entries(path: &Path) -> Vec<String> {
+        let file = File::open(path).unwrap();
+        let reader: Box<dyn Read> = if is_gzip(path) {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut entries: Vec<String> = Archive::new(reader)
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .filter(|path| path.ends_with(".txt"))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_tar_output_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let book = dir.path().join("book");
+        fs::create_dir_all(book.join("chapters")).unwrap();
+        fs::write(book.join("book.txt"), "全文").unwrap();
+        fs::write(book.join("chapters/chapter_1.txt"), "第一章").unwrap();
+
+        for name in ["out.tar", "out.tar.gz"] {
+            let path = dir.path().join(name);
+            let tar = TarOutput::create(&path).unwrap();
+            tar.append_dir("book", &book).unwrap();
+            tar.finish().unwrap();
+            assert!(!dir.path().join(format!("{}.partial", name)).exists());
+            assert_eq!(txt_entries(&path), ["book/book.txt", "book/chapters/chapter_1.txt"]);
+        }
+    }
+
+    #[test]
+    fn test_nested_epubs_keep_relative_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("bundle.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, content) in [
+            ("a/book.epub", "甲"),
+            ("b/book.epub", "乙"),
+            ("a/./book.epub", "重复"),
+            ("notes.txt", "说明"),
+        ] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let nested = extract_nested_epubs(&zip_path).unwrap();
+        let names: Vec<&Path> = nested.epubs.iter().map(|(_, name)| name.as_path()).collect();
+        assert_eq!(names, [Path::new("a/book.epub"), Path::new("b/book.epub")]);
+        // 同名文件各自保留，重复的条目不覆盖第一个
+        let contents: Vec<String> = nested.epubs.iter().map(|(path, _)| fs::read_to_string(path).unwrap()).collect();
+        assert_eq!(contents, ["甲", "乙"]);
+
+        // 同名的 zip 包解压到不同的临时目录
+        let other = extract_nested_epubs(&zip_path).unwrap();
+        assert_ne!(other.epubs[0].0, nested.epubs[0].0);
+
+        let temp_dir = nested.epubs[0].0.parent().unwrap().parent().unwrap().to_path_buf();
+        nested.close().unwrap();
+        assert!(!temp_dir.exists());
+    }
+
+    #[test]
+    fn test_tar_output_reopen_keeps_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        fs::write(first.join("first.txt"), "一").unwrap();
+        fs::write(second.join("second.txt"), "二").unwrap();
+        let long_name = format!("{}.txt", "长".repeat(60));
+        fs::write(second.join(&long_name), "长路径").unwrap();
+
+        for name in ["out.tar", "out.tgz"] {
+            let path = dir.path().join(name);
+            let tar = TarOutput::create(&path).unwrap();
+            tar.append_dir("first", &first).unwrap();
+            tar.finish().unwrap();
+
+            let tar = TarOutput::reopen(&path).unwrap();
+            tar.append_dir("second", &second).unwrap();
+            tar.finish().unwrap();
+            // 再追加一次，超长路径在复制时不被截断
+            TarOutput::reopen(&path).unwrap().finish().unwrap();
+
+            let mut expected = vec![
+                "first/first.txt".to_string(),
+                "second/second.txt".to_string(),
+                format!("second/{}", long_name),
+            ];
+            expected.sort();
+            assert_eq!(txt_entries(&path), expected);
+        }
+    }
+}
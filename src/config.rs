@@ -1,32 +1,52 @@
-use std::sync::LazyLock;
+use std::path::Path;
+use std::sync::OnceLock;
 
-use ahash::AHashSet;
+use ahash::{AHashMap, AHashSet};
 use anyhow::{Context, Result};
+use regex::Regex;
 use serde::Deserialize;
 use serde::de::Deserializer;
 
-static CONFIG: LazyLock<Config> = LazyLock::new(|| {
-    Config::load().expect("Failed to load configuration")
-});
+static CONFIG: OnceLock<Config> = OnceLock::new();
 
 #[derive(Debug, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub output_dir: String,
-    pub input_dir: String,
+    /// 输入目录，配置文件中可以写一个目录或目录列表
+    #[serde(deserialize_with = "deserialize_input_dirs")]
+    pub input_dir: Vec<String>,
     pub separator: String,
     pub tags: Tags,
     pub options: Options,
+    /// 创作者角色代码到名称的映射，加载时合并到内置的 [`DEFAULT_ROLES`] 之上
+    #[serde(deserialize_with = "deserialize_roles")]
+    pub roles: AHashMap<String, String>,
 }
 
 impl Config {
+    /// 从当前目录的 config.toml 加载，文件不存在时使用默认配置
     pub fn load() -> Result<Self> {
+        Self::build(
+            config::File::with_name("config")
+                .format(config::FileFormat::Toml)
+                .required(false),
+        )
+    }
+
+    /// 从指定的配置文件加载，文件必须存在
+    pub fn load_from(path: &Path) -> Result<Self> {
+        Self::build(
+            config::File::from(path)
+                .format(config::FileFormat::Toml)
+                .required(true),
+        )
+        .with_context(|| format!("Failed to load config from {}", path.display()))
+    }
+
+    fn build(source: config::File<config::FileSourceFile, config::FileFormat>) -> Result<Self> {
         config::Config::builder()
-            .add_source(
-                config::File::with_name("config")
-                    .format(config::FileFormat::Toml)
-                    .required(false),
-            )
+            .add_source(source)
             .build()?
             .try_deserialize()
             .with_context(|| anyhow::anyhow!("Failed to load config"))
@@ -37,10 +57,11 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             output_dir: "output".to_string(),
-            input_dir: "input".to_string(),
+            input_dir: vec!["input".to_string()],
             separator: "".to_string(),
             tags: Tags::default(),
             options: Options::default(),
+            roles: default_roles(),
         }
     }
 }
@@ -51,6 +72,90 @@ pub struct Options {
     pub split: bool,
     pub combine: bool,
     pub metadata: bool,
+    pub watch: bool,
+    pub volume_max_bytes: Option<usize>,
+    pub synthesize_titles: bool,
+    pub title_template: String,
+    pub strip_page_numbers: bool,
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub page_number_pattern: Regex,
+    pub stdout: bool,
+    pub anchor_markers: bool,
+    pub verify: bool,
+    pub unwrap_lines: bool,
+    pub source_hash: bool,
+    pub title_underline: Option<char>,
+    pub parallel_columns: bool,
+    pub parallel_stride: usize,
+    pub column_separator: String,
+    pub log_file: Option<String>,
+    pub emphasis_markers: bool,
+    pub reading_speed: Option<ReadingSpeed>,
+    pub language_filter: bool,
+    pub language_confidence: f64,
+    pub sentinels: bool,
+    pub trim_empty_edges: bool,
+    pub escape_output: bool,
+    pub escape_quotes: bool,
+    pub top_chapters: Option<usize>,
+    pub include_parts: bool,
+    pub skip_teasers: bool,
+    #[serde(deserialize_with = "deserialize_regexes")]
+    pub teaser_patterns: Vec<Regex>,
+    pub teaser_threshold: f64,
+    pub source_boundaries: bool,
+    pub collapse_spaces: bool,
+    pub output_tar: Option<String>,
+    pub toc_only: bool,
+    pub detect_mojibake: bool,
+    pub mojibake_threshold: f64,
+    pub author_name: AuthorName,
+    pub preserve_mtime: bool,
+    pub format: OutputFormat,
+    pub br_marker: Option<String>,
+    pub media_index: bool,
+    pub metadata_trailer: bool,
+    pub html_parser: HtmlParser,
+    pub split_index: bool,
+    pub scan_nested_zips: bool,
+    /// 合并文件中章节标题的模板，也可以写作 combined_header
+    #[serde(alias = "combined_header")]
+    pub heading_format: Option<String>,
+    pub toc_as_headings: bool,
+    pub output_encoding: OutputEncoding,
+    pub titlepage_metadata: bool,
+    pub unique_paragraphs: bool,
+    pub sort_paragraphs: bool,
+    pub provenance_jsonl: bool,
+    #[serde(deserialize_with = "deserialize_id_patterns")]
+    pub exclude_ids: Vec<Regex>,
+    pub dry_run: bool,
+    pub pad_chapter_numbers: bool,
+    pub strict_mimetype: bool,
+    pub recursive: bool,
+    pub jobs: Option<usize>,
+    pub incremental: bool,
+    pub creator_separator: Option<String>,
+    pub max_blank_lines: Option<usize>,
+    pub line_ending: LineEnding,
+    pub skip_tags: Vec<String>,
+    pub ruby_mode: RubyMode,
+    pub endnotes: bool,
+    pub image_placeholders: bool,
+    pub list_markers: bool,
+    pub replace_nbsp: bool,
+    pub strip_soft_hyphens: bool,
+    pub strip_zero_width: bool,
+    pub normalize_nfc: bool,
+    pub min_chapter_chars: usize,
+    pub toc: bool,
+    pub scene_break: String,
+    pub include_non_linear: bool,
+    pub dedup_chapters: bool,
+    pub stats: bool,
+    pub stats_file: bool,
+    pub quiet: bool,
+    pub atomic_write: bool,
 }
 
 impl Default for Options {
@@ -59,6 +164,200 @@ impl Default for Options {
             split: true,
             combine: true,
             metadata: true,
+            watch: false,
+            volume_max_bytes: None,
+            synthesize_titles: true,
+            title_template: "Chapter {index}".to_string(),
+            strip_page_numbers: false,
+            page_number_pattern: Regex::new(r"^\s*\d+\s*$").unwrap(),
+            stdout: false,
+            anchor_markers: false,
+            verify: false,
+            unwrap_lines: false,
+            source_hash: false,
+            title_underline: None,
+            parallel_columns: false,
+            parallel_stride: 2,
+            column_separator: "\t".to_string(),
+            log_file: None,
+            emphasis_markers: false,
+            reading_speed: None,
+            language_filter: false,
+            language_confidence: 0.9,
+            sentinels: false,
+            trim_empty_edges: false,
+            escape_output: false,
+            escape_quotes: false,
+            top_chapters: None,
+            include_parts: false,
+            skip_teasers: false,
+            teaser_patterns: [
+                r"https?://|www\.",
+                r"(?i)continued (on|at)\b",
+                r"(?i)read (more|the rest|the next chapter) (on|at)\b",
+                r"(?i)sign up for our newsletter",
+                r"最新章节|请访问|请到.*阅读",
+            ]
+            .into_iter()
+            .map(|pattern| Regex::new(pattern).unwrap())
+            .collect(),
+            teaser_threshold: 0.5,
+            source_boundaries: false,
+            collapse_spaces: false,
+            output_tar: None,
+            toc_only: false,
+            detect_mojibake: false,
+            mojibake_threshold: 0.01,
+            author_name: AuthorName::default(),
+            preserve_mtime: false,
+            format: OutputFormat::default(),
+            br_marker: None,
+            media_index: false,
+            metadata_trailer: false,
+            html_parser: HtmlParser::default(),
+            split_index: false,
+            scan_nested_zips: false,
+            heading_format: None,
+            toc_as_headings: false,
+            output_encoding: OutputEncoding::default(),
+            titlepage_metadata: false,
+            unique_paragraphs: false,
+            sort_paragraphs: true,
+            provenance_jsonl: false,
+            exclude_ids: vec![anchored(DEFAULT_EXCLUDE_ID).unwrap()],
+            dry_run: false,
+            pad_chapter_numbers: true,
+            strict_mimetype: false,
+            recursive: false,
+            jobs: None,
+            incremental: false,
+            creator_separator: None,
+            max_blank_lines: Some(1),
+            line_ending: LineEnding::default(),
+            skip_tags: vec!["script".to_string(), "style".to_string()],
+            ruby_mode: RubyMode::default(),
+            endnotes: false,
+            image_placeholders: false,
+            list_markers: false,
+            replace_nbsp: false,
+            strip_soft_hyphens: false,
+            strip_zero_width: false,
+            normalize_nfc: false,
+            min_chapter_chars: 0,
+            toc: false,
+            scene_break: "* * *".to_string(),
+            include_non_linear: false,
+            dedup_chapters: true,
+            stats: false,
+            stats_file: false,
+            quiet: false,
+            atomic_write: false,
+        }
+    }
+}
+
+/// 输出文本的格式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// 每个段落一行
+    #[default]
+    Text,
+    /// 每个句子一行，重新转换时的改动在 diff 中更清晰
+    Sentences,
+    /// 保留标题、强调、列表和引用的 Markdown，输出文件的扩展名为 `.md`
+    Markdown,
+    /// 每章输出为带 YAML front matter 的 Markdown 笔记，供 Obsidian 等笔记软件导入
+    Obsidian,
+    /// 整本书输出为一个 JSON 文件，包含元数据和所有章节的标题与正文
+    Json,
+}
+
+impl OutputFormat {
+    /// 正文是否转换为 Markdown（标题、链接、强调、列表、引用）
+    pub fn is_markdown(self) -> bool {
+        matches!(self, OutputFormat::Markdown | OutputFormat::Obsidian)
+    }
+
+    /// 输出文件的扩展名
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            format if format.is_markdown() => "md",
+            _ => "txt",
+        }
+    }
+}
+
+/// 输出文件的编码
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum OutputEncoding {
+    #[default]
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// 带 BOM 的 UTF-16 LE，供只认这种编码的旧版 Windows 程序使用
+    #[serde(rename = "utf-16le")]
+    Utf16le,
+}
+
+/// 输出文件的换行风格
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    /// 供只认 `\r\n` 的 Windows 程序使用
+    Crlf,
+}
+
+/// `<ruby>` 注音（`<rt>`）的处理方式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RubyMode {
+    /// 只保留正文，丢弃注音
+    #[default]
+    Drop,
+    /// 注音放在正文后的括号中，如 `漢字(かんじ)`
+    Parentheses,
+}
+
+/// 章节使用的解析器
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HtmlParser {
+    /// 按 XML 解析，要求文档格式基本正确
+    #[default]
+    Strict,
+    /// 按 HTML5 解析，容忍格式错误
+    Lenient,
+}
+
+/// 写入作者名时使用的形式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthorName {
+    /// 显示名，如 "Stephen King"
+    #[default]
+    Display,
+    /// 用于排序的 file-as 形式，如 "King, Stephen"，没有时退回显示名
+    FileAs,
+}
+
+/// 阅读速度
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ReadingSpeed {
+    /// 以空格分词的语言，每分钟阅读的词数
+    pub words_per_minute: usize,
+    /// 中文、日文，每分钟阅读的字数
+    pub chars_per_minute: usize,
+}
+
+impl Default for ReadingSpeed {
+    fn default() -> Self {
+        Self {
+            words_per_minute: 250,
+            chars_per_minute: 500,
         }
     }
 }
@@ -140,6 +439,108 @@ impl Default for RawTags {
     }
 }
 
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+/// 默认排除的封面：id 为 `cover` 或以 `-`、`_`、`.` 与其他部分分隔的 `cover`，如 `cover.xhtml`、`titlepage_cover`
+const DEFAULT_EXCLUDE_ID: &str = r"(?i)(.*[-_.])?cover([-_.].*)?";
+
+/// 模式需要与整个 id 匹配
+fn anchored(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+fn deserialize_id_patterns<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| anchored(pattern).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// 内置的 MARC 角色代码映射
+const DEFAULT_ROLES: [(&str, &str); 4] = [
+    ("aut", "author"),
+    ("edt", "editor"),
+    ("trl", "translator"),
+    ("ill", "illustrator"),
+];
+
+fn default_roles() -> AHashMap<String, String> {
+    DEFAULT_ROLES
+        .iter()
+        .map(|(code, name)| (code.to_string(), name.to_string()))
+        .collect()
+}
+
+/// 配置中的角色映射覆盖同名的内置映射，其余内置映射保留
+fn deserialize_roles<'de, D>(deserializer: D) -> Result<AHashMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut roles = default_roles();
+    roles.extend(AHashMap::<String, String>::deserialize(deserializer)?);
+    Ok(roles)
+}
+
+fn deserialize_input_dirs<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum InputDirs {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match InputDirs::deserialize(deserializer)? {
+        InputDirs::One(dir) => vec![dir],
+        InputDirs::Many(dirs) => dirs,
+    })
+}
+
+fn deserialize_regexes<'de, D>(deserializer: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// 设置全局配置，必须在第一次调用 [`get_config`] 之前调用
+pub fn init_config(config: Config) -> Result<()> {
+    CONFIG
+        .set(config)
+        .map_err(|_| anyhow::anyhow!("Configuration already initialized"))
+}
+
+/// 未调用 [`init_config`] 时从当前目录的 config.toml 加载
 pub fn get_config() -> &'static Config {
-    &CONFIG
+    CONFIG.get_or_init(|| Config::load().expect("Failed to load configuration"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combined_header_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[options]\ncombined_header = \"## {title}\"\n").unwrap();
+
+        // 旧配置中的 combined_header 仍然读取为 heading_format
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.options.heading_format.as_deref(), Some("## {title}"));
+    }
 }
@@ -1,5 +1,7 @@
+pub mod archive;
 mod config;
 pub mod process;
 mod utils;
 
-pub use config::get_config;
+pub use config::{Config, OutputFormat, get_config, init_config};
+pub use process::{Chapter, Epub};
@@ -1,67 +1,588 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Write};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use env_logger::{Target, WriteStyle};
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{DebouncedEventKind, new_debouncer};
+use rayon::iter::Either;
+use rayon::ThreadPoolBuilder;
 use rayon::prelude::*;
+use walkdir::WalkDir;
 
-use epub2txt::get_config;
+use epub2txt::archive::{NestedEpubs, TarOutput, extract_nested_epubs};
+use epub2txt::{Config, OutputFormat, get_config, init_config};
 use epub2txt::process;
+use epub2txt::process::{TextStats, WriteReport};
+
+/// 将 EPUB 转换为纯文本
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// 要处理的 EPUB 文件或 glob 模式（如 '~/books/**/Series*.epub'），指定后不再扫描输入目录
+    patterns: Vec<String>,
+    /// 输入目录，可重复指定多个，优先于配置文件中的 input_dir
+    #[arg(short, long)]
+    input: Vec<PathBuf>,
+    /// 输出目录，优先于配置文件中的 output_dir
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// 配置文件路径，默认为当前目录下的 config.toml
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// 只列出将要处理的书籍、章节数和输出路径，不写入任何文件
+    #[arg(long)]
+    dry_run: bool,
+    /// 输出格式，优先于配置文件中的 format
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+    /// 递归扫描输入目录的所有子目录
+    #[arg(short, long)]
+    recursive: bool,
+    /// 跳过输出已存在且不早于源文件的书籍
+    #[arg(long)]
+    incremental: bool,
+    /// 输出每本书的章节数、字符数和词数
+    #[arg(long)]
+    stats: bool,
+    /// 不输出进度和汇总等提示信息，错误和警告仍写入日志
+    #[arg(short, long)]
+    quiet: bool,
+    /// 汇总的格式，json 时在最后向标准输出写入一个 JSON 对象，不输出其他提示信息
+    #[arg(long, value_enum, default_value_t = Summary::Text)]
+    summary: Summary,
+    /// 同时处理的书籍数，1 表示按顺序逐本处理
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+}
+
+/// 处理完成后的汇总格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Summary {
+    /// 带表情符号的提示信息
+    Text,
+    /// 供脚本解析的 JSON
+    Json,
+}
+
+/// 不转换，只查看单本书的子命令
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 以 TOML 格式输出书籍的元数据、章节数和 spine 中的章节路径
+    Info {
+        /// EPUB 文件路径
+        file: PathBuf,
+    },
+    /// 按 spine 顺序列出章节的序号、zip 内路径和标题，以制表符分隔
+    List {
+        /// EPUB 文件路径
+        file: PathBuf,
+    },
+}
 
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+    // 优先级：命令行参数 > 配置文件 > 默认值
+    let mut config = match &cli.config {
+        Some(path) => Config::load_from(path)?,
+        None => Config::load()?,
+    };
+    if !cli.input.is_empty() {
+        config.input_dir = cli.input.iter().map(|input| input.to_string_lossy().into_owned()).collect();
+    }
+    if let Some(output) = &cli.output {
+        config.output_dir = output.to_string_lossy().into_owned();
+    }
+    if cli.dry_run {
+        config.options.dry_run = true;
+    }
+    if let Some(format) = cli.format {
+        config.options.format = format;
+    }
+    if cli.recursive {
+        config.options.recursive = true;
+    }
+    if cli.incremental {
+        config.options.incremental = true;
+    }
+    if cli.stats {
+        config.options.stats = true;
+    }
+    if cli.quiet || cli.summary == Summary::Json {
+        config.options.quiet = true;
+    }
+    if let Some(jobs) = cli.jobs {
+        config.options.jobs = Some(jobs.get());
+    }
+    init_config(config)?;
+
+    init_logger()?;
+
+    if let Some(command) = cli.command {
+        return match command {
+            Command::Info { file } => {
+                print!("{}", process::Epub::from_file(file)?.info());
+                Ok(())
+            }
+            Command::List { file } => {
+                let titles = process::Epub::from_file(file)?.chapter_titles()?;
+                for (index, (path, title)) in titles.iter().enumerate() {
+                    println!("{}\t{}\t{}", index + 1, path, title.replace(['\t', '\n'], " "));
+                }
+                Ok(())
+            }
+        };
+    }
+
     let start = Instant::now();
-    let input_dir = Path::new(&get_config().input_dir);
-    if !(input_dir.exists() && input_dir.is_dir()) {
-        anyhow::bail!("Input directory does not exist or is not a directory");
+    let input_dirs: Vec<PathBuf> = get_config().input_dir.iter().map(PathBuf::from).collect();
+    if cli.patterns.is_empty() {
+        for input_dir in &input_dirs {
+            if !(input_dir.exists() && input_dir.is_dir()) {
+                anyhow::bail!("Input directory does not exist or is not a directory: {}", input_dir.display());
+            }
+        }
     }
+    let input_dirs = canonical_dirs(&input_dirs);
 
-    let tasks = get_tasks(input_dir)?;
+    // zip 包中的 EPUB 解压到各自的临时目录，处理完成后删除
+    let (tasks, nested) = if cli.patterns.is_empty() {
+        let options = &get_config().options;
+        get_tasks(&input_dirs, options.recursive, options.scan_nested_zips)
+    } else {
+        let tasks = glob_files(&cli.patterns)
+            .into_iter()
+            .map(|path| {
+                let subdir = output_subdir(&path, &input_dirs);
+                (path, subdir)
+            })
+            .collect();
+        (tasks, Vec::new())
+    };
+
+    let options = &get_config().options;
+    let tar_path = options
+        .output_tar
+        .as_deref()
+        .filter(|_| !options.stdout && !options.dry_run);
+    if tar_path.is_some() && options.incremental {
+        // 每次运行都重新生成归档，跳过的书籍会从归档中消失
+        log::warn!("incremental is ignored when output_tar is set, all books are written to the archive");
+    }
+    let tar = tar_path.map(TarOutput::create).transpose()?;
 
-    tasks
-        .into_par_iter()
-        .map(process_epub)
-        .collect::<Result<Vec<()>>>()?;
+    // 未设置 jobs 时使用 rayon 的全局线程池；num_threads(0) 同样表示 CPU 核心数
+    let pool = match options.jobs {
+        Some(jobs) => Some(ThreadPoolBuilder::new().num_threads(jobs).build()?),
+        None => None,
+    };
+    // 单本书失败不影响其他书籍，全部处理完后再汇总失败的文件
+    let total = tasks.len();
+    let run = || {
+        tasks
+            .into_par_iter()
+            .partition_map(|(path, subdir)| match process_epub(path.clone(), subdir, tar.as_ref()) {
+                Ok(report) => Either::Left((path, report)),
+                Err(e) => {
+                    log::error!("Failed to process {}: {:#}", path.display(), e);
+                    Either::Right((path, e))
+                }
+            })
+    };
+    let (reports, failures): (Vec<_>, Vec<_>) = match &pool {
+        Some(pool) => pool.install(run),
+        None => run(),
+    };
+
+    if let Some(tar) = tar {
+        tar.finish()?;
+    }
+    for nested in nested {
+        nested.close()?;
+    }
 
     let duration = start.elapsed();
 
+    let skipped = reports.iter().filter(|(_, book)| book.skipped).count();
+    if skipped > 0 {
+        report(&format!(
+            "📚 成功处理 {}/{} 本书，其中 {} 本输出已是最新，已跳过",
+            reports.len(),
+            total,
+            skipped
+        ));
+    } else {
+        report(&format!("📚 成功处理 {}/{} 本书", reports.len(), total));
+    }
+    if options.stats {
+        let mut total = TextStats::default();
+        let mut chapters = 0;
+        for (_, stats_report) in &reports {
+            if let Some(stats) = &stats_report.stats {
+                total.merge(stats);
+                chapters += stats_report.written;
+            }
+        }
+        report(&format!("📊 合计 {} 章，{} 字符，{} 词", chapters, total.chars, total.words));
+    }
     display_elapsed_time(duration);
+    if !failures.is_empty() {
+        let mut message = format!("❌ {} 本书处理失败:", failures.len());
+        for (path, e) in &failures {
+            message.push_str(&format!("\n   {}: {:#}", path.display(), e));
+        }
+        report(&message);
+    }
+
+    if options.verify && !options.dry_run {
+        let incomplete = reports.iter().filter(|(_, book)| !book.is_complete()).count();
+        if incomplete > 0 {
+            report(&format!("⚠️ {} 本书的章节数与预期不符", incomplete));
+        } else {
+            report("✅ 所有书籍的章节数均与预期一致");
+        }
+    }
+
+    if cli.summary == Summary::Json {
+        let summary = json_summary(&reports, &failures, duration);
+        if options.stdout {
+            eprintln!("{}", summary);
+        } else {
+            println!("{}", summary);
+        }
+    }
+
+    if get_config().options.watch {
+        watch(&input_dirs, tar_path)?;
+    }
 
+    if !failures.is_empty() {
+        anyhow::bail!("{} of {} books failed", failures.len(), total);
+    }
     Ok(())
 }
 
-fn process_epub(epub_path: PathBuf) -> anyhow::Result<()> {
+fn init_logger() -> Result<()> {
+    // 默认输出 warn 及以上级别的日志，可通过 RUST_LOG 调整
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+
+    if let Some(log_file) = &get_config().options.log_file {
+        let file = File::options().create(true).append(true).open(log_file)?;
+        // 每条日志一行：时间、级别、内容（内容以书名开头）
+        // env_logger 会对 Pipe 目标加锁，并行任务的日志不会交错
+        builder
+            .format(|buf, record| {
+                writeln!(buf, "{}\t{}\t{}", buf.timestamp(), record.level(), record.args())
+            })
+            .write_style(WriteStyle::Never)
+            .target(Target::Pipe(Box::new(TeeWriter { file })));
+    }
+
+    builder.init();
+    Ok(())
+}
+
+/// 同时写入标准错误和日志文件
+struct TeeWriter {
+    file: File,
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stderr().write_all(buf)?;
+        self.file.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()?;
+        self.file.flush()
+    }
+}
+
+/// 设置了 `tar_path` 时，每转换一本书就追加到已有的归档中
+fn watch(input_dirs: &[PathBuf], tar_path: Option<&str>) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    // 文件可能仍在写入，等待事件平静一段时间后再处理
+    let mut debouncer = new_debouncer(Duration::from_secs(2), tx)?;
+    let mode = if get_config().options.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    for input_dir in input_dirs {
+        debouncer.watcher().watch(input_dir, mode)?;
+    }
+    let dirs: Vec<String> = input_dirs.iter().map(|dir| dir.display().to_string()).collect();
+    report(&format!("👀 正在监听目录: {}", dirs.join(", ")));
+
+    for result in rx {
+        let events = match result {
+            Ok(events) => events,
+            Err(e) => {
+                log::error!("Watch error: {}", e);
+                continue;
+            }
+        };
+
+        for event in events {
+            // AnyContinuous 表示文件仍在持续变化，等待最终的 Any 事件
+            if event.kind != DebouncedEventKind::Any {
+                continue;
+            }
+            let path = event.path;
+            if !(path.is_file() && path.extension().is_some_and(|ext| ext == "epub")) {
+                continue;
+            }
+            let result = match tar_path {
+                Some(tar_path) => TarOutput::reopen(tar_path).and_then(|tar| {
+                    process_epub(path.clone(), output_subdir(&path, input_dirs), Some(&tar))?;
+                    tar.finish()
+                }),
+                None => process_epub(path.clone(), output_subdir(&path, input_dirs), None).map(|_| ()),
+            };
+            match result {
+                Ok(()) => report(&format!("✅ 已转换: {}", path.display())),
+                Err(e) => log::error!("Failed to process {}: {:#}", path.display(), e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 设置了 `tar` 时，书籍先输出到临时目录再加入归档，输出目录中不会出现散落的文件
+/// `subdir` 为输出目录下的子目录，见 [`output_subdir`]
+fn process_epub(epub_path: PathBuf, subdir: PathBuf, tar: Option<&TarOutput>) -> anyhow::Result<WriteReport> {
     let mut epub = process::Epub::from_file(epub_path)?;
-    epub.write()
+    epub.subdir = subdir;
+    let staging = match tar {
+        Some(_) => Some(tempfile::tempdir()?),
+        None => None,
+    };
+    if let Some(staging) = &staging {
+        epub.output_root = staging.path().to_path_buf();
+    }
+    if get_config().options.incremental && tar.is_none() && epub.is_up_to_date() {
+        log::info!("{}: output is up to date, skipped", epub.filename);
+        return Ok(WriteReport {
+            skipped: true,
+            ..WriteReport::default()
+        });
+    }
+    if get_config().options.dry_run {
+        report(&epub.plan().to_string());
+    }
+    let report = epub.write()?;
+    if let Some(stats) = &report.stats {
+        crate::report(&format!(
+            "📊 {}: {} 章，{} 字符，{} 词",
+            epub.filename, report.written, stats.chars, stats.words
+        ));
+    }
+
+    if let (Some(tar), Some(staging)) = (tar, staging) {
+        let name = epub.subdir.join(&epub.filename);
+        let output_dir = staging.path().join(&name);
+        if output_dir.exists() {
+            tar.append_dir(&name.to_string_lossy(), &output_dir)?;
+        }
+        staging.close()?;
+    }
+    Ok(report)
 }
 
-fn get_tasks(input_dir: &Path) -> Result<Vec<PathBuf>> {
-    let epub_paths: Vec<PathBuf> = input_dir
-        .read_dir()?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let path = entry.path();
-            (path.extension()? == "epub").then_some(path)
+/// 输入目录中的 EPUB 及其输出子目录；`scan_nested_zips` 时同时将 `.zip` 中的 EPUB 解压到临时目录并加入任务，
+/// 输出到 `<zip 包所在的子目录>/<zip 包名>/<包内目录>` 下。返回的 [`NestedEpubs`] 在处理完成后才能删除
+fn get_tasks(
+    input_dirs: &[PathBuf],
+    recursive: bool,
+    scan_nested_zips: bool,
+) -> (Vec<(PathBuf, PathBuf)>, Vec<NestedEpubs>) {
+    let files = all_input_files(input_dirs, recursive);
+    let has_extension = |path: &Path, extension: &str| path.extension().is_some_and(|ext| ext == extension);
+    let mut tasks: Vec<(PathBuf, PathBuf)> = files
+        .iter()
+        .filter(|path| has_extension(path, "epub"))
+        .map(|path| (path.clone(), output_subdir(path, input_dirs)))
+        .collect();
+
+    let mut nested = Vec::new();
+    if scan_nested_zips {
+        for path in files.iter().filter(|path| has_extension(path, "zip")) {
+            match extract_nested_epubs(path) {
+                Ok(epubs) => {
+                    let zip_subdir = output_subdir(path, input_dirs).join(path.file_stem().unwrap_or_default());
+                    tasks.extend(epubs.epubs.iter().map(|(epub, name)| {
+                        let subdir = zip_subdir.join(name.parent().unwrap_or(Path::new("")));
+                        (epub.clone(), subdir)
+                    }));
+                    nested.push(epubs);
+                }
+                Err(e) => log::error!("Failed to read {}: {:#}", path.display(), e),
+            }
+        }
+    }
+
+    (tasks, nested)
+}
+
+/// 所有输入目录中的文件，目录互相重叠时按规范化路径去重，保留先扫描到的路径
+fn all_input_files(input_dirs: &[PathBuf], recursive: bool) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    input_dirs
+        .iter()
+        .flat_map(|input_dir| input_files(input_dir, recursive))
+        .filter(|path| seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())))
+        .collect()
+}
+
+/// 展开命令行中的文件路径和 glob 模式，开头的 `~` 替换为用户主目录
+/// 模式无效或没有匹配的文件时只输出警告；多个模式匹配到同一文件时只保留一次
+fn glob_files(patterns: &[String]) -> Vec<PathBuf> {
+    let home = std::env::var("HOME").ok();
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let pattern = match (pattern.strip_prefix("~/"), &home) {
+            (Some(rest), Some(home)) => format!("{}/{}", home, rest),
+            _ => pattern.clone(),
+        };
+        let paths = match glob::glob(&pattern) {
+            Ok(paths) => paths,
+            Err(e) => {
+                log::warn!("Invalid pattern {}: {}", pattern, e);
+                continue;
+            }
+        };
+        let before = files.len();
+        for path in paths {
+            match path {
+                Ok(path) if path.is_file() => {
+                    if seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())) {
+                        files.push(path);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Skipped {}: {}", e.path().display(), e.error()),
+            }
+        }
+        if files.len() == before {
+            log::warn!("No files match {}", pattern);
+        }
+    }
+    files
+}
+
+/// 输入目录中的文件，`recursive` 时包括所有子目录
+/// 跟随符号链接，形成循环的链接会被跳过
+fn input_files(input_dir: &Path, recursive: bool) -> Vec<PathBuf> {
+    WalkDir::new(input_dir)
+        .follow_links(true)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) => entry.file_type().is_file().then(|| entry.into_path()),
+            Err(e) => {
+                log::warn!("Skipped {}: {}", e.path().unwrap_or(input_dir).display(), e);
+                None
+            }
         })
+        .collect()
+}
+
+/// 将输入目录规范化为绝对路径：监听事件中的路径总是绝对路径，相对的输入目录无法与之比较
+/// 无法规范化（如不存在）的目录保持原样
+fn canonical_dirs(input_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    input_dirs
+        .iter()
+        .map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.clone()))
+        .collect()
+}
+
+/// EPUB 所在目录相对于（第一个包含它的）输入目录的路径，不在输入目录下（如从 zip 中解压）时为空
+/// `input_dirs` 应已经过 [`canonical_dirs`] 规范化
+fn output_subdir(epub_path: &Path, input_dirs: &[PathBuf]) -> PathBuf {
+    let Some(parent) = epub_path.parent() else {
+        return PathBuf::new();
+    };
+    let parent = parent.canonicalize().unwrap_or_else(|_| parent.to_path_buf());
+    input_dirs
+        .iter()
+        .find_map(|input_dir| parent.strip_prefix(input_dir).ok())
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+/// `--summary json` 的输出：处理、失败和跳过的书籍数，耗时，以及每本书的章节数和失败原因
+fn json_summary(
+    reports: &[(PathBuf, WriteReport)],
+    failures: &[(PathBuf, anyhow::Error)],
+    duration: Duration,
+) -> serde_json::Value {
+    let books: Vec<serde_json::Value> = reports
+        .iter()
+        .map(|(path, book)| {
+            serde_json::json!({
+                "path": path.display().to_string(),
+                "chapters": book.written,
+                "expected": book.expected,
+                "dropped": book.dropped,
+                "skipped": book.skipped,
+            })
+        })
+        .collect();
+    let failed: Vec<serde_json::Value> = failures
+        .iter()
+        .map(|(path, e)| serde_json::json!({ "path": path.display().to_string(), "error": format!("{:#}", e) }))
         .collect();
+    serde_json::json!({
+        "processed": reports.len(),
+        "failed": failures.len(),
+        "skipped": reports.iter().filter(|(_, book)| book.skipped).count(),
+        "elapsed_ms": duration.as_millis() as u64,
+        "books": books,
+        "failures": failed,
+    })
+}
 
-    Ok(epub_paths)
+/// 输出提示信息；标准输出被用于输出文本时改为输出到标准错误，设置了 quiet 时不输出
+fn report(message: &str) {
+    if get_config().options.quiet {
+        return;
+    }
+    if get_config().options.stdout {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
 }
 
 pub fn display_elapsed_time(duration: std::time::Duration) {
     let total_ms = duration.as_millis();
 
-    if total_ms >= 60000 {
+    let message = if total_ms >= 60000 {
         // 超过1分钟：显示分秒
         let mins = total_ms / 60000;
         let secs = (total_ms % 60000) / 1000;
         let ms_remaining = total_ms % 1000;
 
         if ms_remaining > 0 {
-            println!(
+            format!(
                 "✅ 处理完成！耗时: {}分{}秒{}毫秒",
                 mins, secs, ms_remaining
-            );
+            )
         } else {
-            println!("✅ 处理完成！耗时: {}分{}秒", mins, secs);
+            format!("✅ 处理完成！耗时: {}分{}秒", mins, secs)
         }
     } else if total_ms >= 1000 {
         // 1秒到1分钟：显示秒和毫秒
@@ -69,12 +590,153 @@ pub fn display_elapsed_time(duration: std::time::Duration) {
         let ms_remaining = total_ms % 1000;
 
         if ms_remaining > 0 {
-            println!("✅ 处理完成！耗时: {}秒{}毫秒", secs, ms_remaining);
+            format!("✅ 处理完成！耗时: {}秒{}毫秒", secs, ms_remaining)
         } else {
-            println!("✅ 处理完成！耗时: {}秒", secs);
+            format!("✅ 处理完成！耗时: {}秒", secs)
         }
     } else {
         // 少于1秒：只显示毫秒
-        println!("✅ 处理完成！耗时: {}毫秒", total_ms);
+        format!("✅ 处理完成！耗时: {}毫秒", total_ms)
+    };
+
+    report(&message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_files_recursive() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path();
+        std::fs::create_dir_all(input.join("作者/系列")).unwrap();
+        std::fs::write(input.join("top.epub"), "").unwrap();
+        std::fs::write(input.join("作者/系列/book.epub"), "").unwrap();
+        // 指向上级目录的符号链接会形成循环
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(input, input.join("作者/loop")).unwrap();
+
+        let mut files = input_files(input, true);
+        files.sort();
+        assert_eq!(files, [input.join("top.epub"), input.join("作者/系列/book.epub")]);
+        assert_eq!(input_files(input, false), [input.join("top.epub")]);
+
+        let input_dirs = [input.to_path_buf()];
+        assert_eq!(output_subdir(&files[1], &input_dirs), Path::new("作者/系列"));
+        assert_eq!(output_subdir(&files[0], &input_dirs), Path::new(""));
+        assert_eq!(output_subdir(Path::new("/tmp/nested/book.epub"), &input_dirs), Path::new(""));
+    }
+
+    #[test]
+    fn test_output_subdir_with_relative_input_dir() {
+        // 在当前目录下创建，得到相对路径的输入目录（如默认的 ./input）
+        let dir = tempfile::tempdir_in(".").unwrap();
+        let input = Path::new(".").join(dir.path().file_name().unwrap());
+        std::fs::create_dir_all(input.join("作者")).unwrap();
+        std::fs::write(input.join("作者/book.epub"), "").unwrap();
+
+        // 监听事件给出的是绝对路径，扫描得到的则是相对路径，两者都应匹配
+        let scanned = input.join("作者/book.epub");
+        let event_path = scanned.canonicalize().unwrap();
+        let input_dirs = canonical_dirs(&[input]);
+        assert_eq!(output_subdir(&event_path, &input_dirs), Path::new("作者"));
+        assert_eq!(output_subdir(&scanned, &input_dirs), Path::new("作者"));
+    }
+
+    #[test]
+    fn test_nested_zip_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path();
+        std::fs::create_dir_all(input.join("作者")).unwrap();
+        std::fs::write(input.join("single.epub"), "").unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(input.join("作者/bundle.zip")).unwrap());
+        for name in ["v1/book.epub", "v2/book.epub", "cover.jpg"] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(name.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        let input_dirs = canonical_dirs(&[input.to_path_buf()]);
+
+        let (tasks, nested) = get_tasks(&input_dirs, true, false);
+        assert_eq!(tasks.len(), 1);
+        assert!(nested.is_empty());
+
+        // zip 包中的同名 EPUB 各自输出到 <zip 包所在目录>/<zip 包名>/<包内目录>
+        let (tasks, nested) = get_tasks(&input_dirs, true, true);
+        let mut subdirs: Vec<&Path> = tasks.iter().map(|(_, subdir)| subdir.as_path()).collect();
+        subdirs.sort();
+        assert_eq!(subdirs, [Path::new(""), Path::new("作者/bundle/v1"), Path::new("作者/bundle/v2")]);
+        let extracted: Vec<PathBuf> = tasks.iter().skip(1).map(|(path, _)| path.clone()).collect();
+        assert_eq!(std::fs::read_to_string(&extracted[1]).unwrap(), "v2/book.epub");
+
+        // 处理完成后删除临时目录
+        for nested in nested {
+            nested.close().unwrap();
+        }
+        assert!(extracted.iter().all(|path| !path.exists()));
+    }
+
+    #[test]
+    fn test_json_summary() {
+        let reports = [(
+            PathBuf::from("input/a.epub"),
+            WriteReport {
+                expected: 3,
+                written: 2,
+                dropped: 1,
+                ..WriteReport::default()
+            },
+        )];
+        let failures = [(PathBuf::from("input/b.epub"), anyhow::anyhow!("Not a valid EPUB"))];
+        let summary = json_summary(&reports, &failures, Duration::from_millis(1500));
+        assert_eq!(summary["processed"], 1);
+        assert_eq!(summary["failed"], 1);
+        assert_eq!(summary["elapsed_ms"], 1500);
+        assert_eq!(summary["books"][0]["chapters"], 2);
+        assert_eq!(summary["failures"][0]["error"], "Not a valid EPUB");
+    }
+
+    #[test]
+    fn test_glob_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("作者/Series")).unwrap();
+        std::fs::write(root.join("Series 1.epub"), "").unwrap();
+        std::fs::write(root.join("作者/Series/Series 2.epub"), "").unwrap();
+        std::fs::write(root.join("作者/Other.epub"), "").unwrap();
+
+        let patterns = [
+            format!("{}/**/Series*.epub", root.display()),
+            // 与第一个模式重叠
+            format!("{}/Series 1.epub", root.display()),
+            format!("{}/missing/*.epub", root.display()),
+            format!("{}/[", root.display()),
+        ];
+        let mut files = glob_files(&patterns);
+        files.sort();
+        assert_eq!(files, [root.join("Series 1.epub"), root.join("作者/Series/Series 2.epub")]);
+    }
+
+    #[test]
+    fn test_overlapping_input_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path();
+        std::fs::create_dir_all(input.join("a/sub")).unwrap();
+        std::fs::create_dir_all(input.join("b")).unwrap();
+        std::fs::write(input.join("a/one.epub"), "").unwrap();
+        std::fs::write(input.join("a/sub/two.epub"), "").unwrap();
+        std::fs::write(input.join("b/three.epub"), "").unwrap();
+
+        // a/sub 同时位于 a 之下，同一目录也可能以不同的写法重复给出
+        let input_dirs = [input.join("a"), input.join("a/sub"), input.join("b"), input.join("b/../b")];
+        let mut files = all_input_files(&input_dirs, true);
+        files.sort();
+        assert_eq!(
+            files,
+            [input.join("a/one.epub"), input.join("a/sub/two.epub"), input.join("b/three.epub")]
+        );
+        assert_eq!(output_subdir(&files[1], &input_dirs), Path::new("sub"));
+        assert_eq!(output_subdir(&files[2], &input_dirs), Path::new(""));
     }
 }
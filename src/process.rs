@@ -1,30 +1,119 @@
 mod chapter;
+mod combined;
+mod extract;
+mod json;
+mod language;
+mod markdown;
+mod media;
 mod metadata;
+mod output;
+mod postprocess;
+mod stats;
+mod toc;
 
+pub use chapter::Chapter;
+pub use combined::split_combined;
+pub use stats::TextStats;
+
+use std::fmt;
 use std::fs::File;
-use std::io::BufReader;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek};
 use std::path::PathBuf;
 
+use ahash::AHashSet;
 use anyhow::Result;
 use quick_xml::Reader;
 use quick_xml::events::Event;
-use zip::ZipArchive;
+use zip::{CompressionMethod, ZipArchive};
 
-use crate::config::get_config;
-use crate::utils::normalize_zip_path;
-use chapter::ChapterIter;
+use crate::config::{Config, OutputFormat, get_config};
+use crate::utils::{copy_mtime, normalize_zip_path, sha256_bytes, sha256_file};
+use chapter::{ChapterIter, TrimEmptyEdges};
+use combined::{
+    CombinedWriter, Provenance, line_toc, metadata_trailer_chunk, parallel_rows, provenance_jsonl, sentinel_chunk,
+    source_boundary,
+};
+use json::BookJson;
+use markdown::FrontMatter;
 use metadata::{Metadata, Package};
+use output::{write_atomic, write_text};
+use postprocess::UniqueParagraphs;
+use stats::{format_reading_time, longest, reading_minutes};
+use toc::{TocEntry, TocFormat};
+
+/// 单本书的写入结果
+#[derive(Debug, Default)]
+pub struct WriteReport {
+    /// 由 spine 得出的预期章节数
+    pub expected: usize,
+    /// 实际写入的章节数
+    pub written: usize,
+    /// 按配置有意丢弃的章节数
+    pub dropped: usize,
+    /// 增量模式下输出已是最新，没有处理
+    pub skipped: bool,
+    /// 启用 stats 时，已写入章节的字数统计
+    pub stats: Option<TextStats>,
+}
+
+impl WriteReport {
+    pub fn is_complete(&self) -> bool {
+        self.written + self.dropped == self.expected
+    }
+}
+
+/// 试运行时一本书的输出计划，不创建任何文件
+#[derive(Debug)]
+pub struct OutputPlan {
+    pub filename: String,
+    /// spine 中的章节数
+    pub chapters: usize,
+    /// spine 引用了但 EPUB 中不存在的文件
+    pub missing: Vec<String>,
+    /// 将要写入的文件或目录
+    pub targets: Vec<String>,
+}
 
-pub struct Epub {
+impl fmt::Display for OutputPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "📖 {}: {} 个章节", self.filename, self.chapters)?;
+        for target in &self.targets {
+            write!(f, "\n   → {}", target)?;
+        }
+        for path in &self.missing {
+            write!(f, "\n   ⚠️ 缺少文件: {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// 打开的 EPUB，`R` 为 zip 数据的来源，默认为文件
+/// 提取和输出都使用打开时传入的配置，不读取全局配置
+pub struct Epub<'c, R: Read + Seek = File> {
     pub filename: String,
-    pub archive: ZipArchive<File>,
+    /// 源 EPUB 文件的路径
+    pub source_path: PathBuf,
+    pub archive: ZipArchive<R>,
     pub metadata: Metadata,
     pub chapters: Vec<String>,
+    /// 保留层级结构的目录，没有目录或解析失败时为空
+    pub toc: Vec<TocEntry>,
+    /// 目录的来源格式，没有目录时为 None
+    pub toc_format: Option<TocFormat>,
+    /// 输出的根目录，默认为配置中的 output_dir；打包为 tar 归档时为临时目录
+    pub output_root: PathBuf,
+    /// 输出目录下的子目录，递归扫描时为 EPUB 相对于输入目录的位置
+    pub subdir: PathBuf,
+    pub config: &'c Config,
 }
 
-impl Epub {
+impl<'c> Epub<'c> {
+    /// 使用全局配置打开 EPUB 文件
     pub fn from_file(epub_path: PathBuf) -> Result<Self> {
+        Self::from_file_with_config(epub_path, get_config())
+    }
+
+    pub fn from_file_with_config(epub_path: PathBuf, config: &'c Config) -> Result<Self> {
         let filename = epub_path
             .file_stem()
             .and_then(|s| s.to_str())
@@ -32,34 +121,110 @@ impl Epub {
         let filename = filename.to_string();
 
         let file = File::open(&epub_path)?;
+        let mut epub = Self::open(ZipArchive::new(file)?, filename, epub_path, config)?;
+        if config.options.source_hash {
+            epub.metadata.source_sha256 = Some(sha256_file(&epub.source_path)?);
+        }
+        Ok(epub)
+    }
+}
+
+impl<'c> Epub<'c, Cursor<Vec<u8>>> {
+    /// 从内存中的字节（如上传的文件）打开 EPUB，使用全局配置
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        Self::from_bytes_with_config(bytes, get_config())
+    }
+
+    pub fn from_bytes_with_config(bytes: Vec<u8>, config: &'c Config) -> Result<Self> {
+        let source_sha256 = config.options.source_hash.then(|| sha256_bytes(&bytes));
+        let mut epub = Self::from_reader_with_config(Cursor::new(bytes), config)?;
+        epub.metadata.source_sha256 = source_sha256;
+        Ok(epub)
+    }
+}
+
+impl<'c, R: Read + Seek> Epub<'c, R> {
+    /// 从任意可随机读取的来源（如内存中的 `Cursor<Vec<u8>>`）打开 EPUB，不访问文件系统
+    /// 没有文件名，日志和输出路径中使用 `book`；使用全局配置
+    pub fn from_reader(reader: R) -> Result<Self> {
+        Self::from_reader_with_config(reader, get_config())
+    }
 
-        let mut epub = ZipArchive::new(file)?;
+    pub fn from_reader_with_config(reader: R, config: &'c Config) -> Result<Self> {
+        Self::open(ZipArchive::new(reader)?, "book".to_string(), PathBuf::new(), config)
+    }
 
+    fn open(mut epub: ZipArchive<R>, filename: String, source_path: PathBuf, config: &'c Config) -> Result<Self> {
+        if let Some(problem) = Self::check_mimetype(&mut epub) {
+            if config.options.strict_mimetype {
+                anyhow::bail!("Not a valid EPUB: {}", problem);
+            }
+            log::warn!("{}: {}", filename, problem);
+        }
+        if Self::is_drm_protected(&mut epub)? {
+            anyhow::bail!("EPUB appears to be DRM-protected (encryption.xml present)");
+        }
         let opf_path = Self::extract_opf_path(&mut epub)?;
         let package = {
             let mut opf_file = epub.by_name(&opf_path)?;
             Package::from_opf(&mut opf_file)?
         };
 
-        let idhref_map = package.manifest.into_map();
-        let spine_hrefs = package.spine.into_hrefs(idhref_map);
-        let metadata = package.metadata;
+        let toc_file = package
+            .manifest
+            .toc_href(package.spine.toc.as_deref())
+            .map(|(href, format)| (normalize_zip_path(&opf_path, href.to_string()), format));
+        let toc = match &toc_file {
+            Some((toc_path, format)) => Self::extract_toc(&mut epub, toc_path, *format).unwrap_or_else(|e| {
+                log::warn!("{}: failed to parse table of contents {}: {}", filename, toc_path, e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        let toc_format = toc_file.map(|(_, format)| format);
 
-        let chapters = spine_hrefs
+        for (href, media_type) in package.manifest.unsupported_in(&package.spine, &config.options.exclude_ids) {
+            log::warn!("{}: skipped spine item {} with unsupported media type {}", filename, href, media_type);
+        }
+        let idhref_map = package.manifest.into_map(&config.options.exclude_ids);
+        let spine_hrefs = package.spine.into_hrefs(idhref_map, config.options.include_non_linear);
+        let mut metadata = package.metadata;
+
+        let mut chapters = spine_hrefs
             .into_iter()
             .map(|href| normalize_zip_path(&opf_path, href))
             .collect::<Vec<String>>();
+        if config.options.dedup_chapters {
+            let mut seen = AHashSet::new();
+            chapters.retain(|chapter| seen.insert(chapter.clone()));
+        }
+
+        if config.options.titlepage_metadata
+            && let Some(first) = chapters.first()
+        {
+            match Chapter::extract_chapter(&mut epub, first, config) {
+                Ok(page) => metadata.fill_from_titlepage(&page.title, &page.content),
+                Err(e) => log::warn!("{}: failed to read title page {}: {}", filename, first, e),
+            }
+        }
 
         Ok(Self {
             metadata,
             archive: epub,
             filename,
+            source_path,
             chapters,
+            toc,
+            toc_format,
+            output_root: PathBuf::from(&config.output_dir),
+            subdir: PathBuf::new(),
+            config,
         })
     }
 
+    /// 书籍的输出目录，不存在时创建
     pub fn output_dir(&self) -> Result<PathBuf> {
-        let output_dir = PathBuf::from(&get_config().output_dir).join(&self.filename);
+        let output_dir = self.output_path();
         if !output_dir.exists() {
             std::fs::create_dir_all(&output_dir)?;
         }
@@ -76,68 +241,661 @@ impl Epub {
 
     pub fn total_path(&self) -> Result<PathBuf> {
         let output_dir = self.output_dir()?;
-        let total_path = output_dir.join(&self.filename).with_extension("txt");
-        if !total_path.exists() {
-            File::create(&total_path)?;
+        Ok(output_dir.join(format!("{}.{}", self.filename, self.config.options.format.extension())))
+    }
+
+    /// 设置了 stats_file 时在输出目录中写入 stats.toml
+    fn write_stats(&self, stats: &TextStats, chapters: usize) -> Result<()> {
+        let options = &self.config.options;
+        if options.stats_file && !options.stdout {
+            write_text(&self.output_dir()?.join("stats.toml"), &stats.to_document(chapters).to_string(), options)?;
         }
-        Ok(total_path)
+        Ok(())
     }
 
     pub fn write_metadata(&self) -> Result<()> {
         let output_dir = self.output_dir()?;
-        self.metadata.write(&output_dir)
+        self.metadata.write(&output_dir, self.config)
     }
 
-    pub fn write(&mut self) -> Result<()> {
-        if get_config().options.metadata {
-            self.write_metadata()?;
+    fn output_path(&self) -> PathBuf {
+        self.output_root.join(&self.subdir).join(&self.filename)
+    }
+
+    /// 按当前配置列出将要写入的文件，并检查 spine 中的章节是否都存在
+    pub fn plan(&self) -> OutputPlan {
+        let options = &self.config.options;
+        let output_dir = self.output_path();
+        let path = |name: &str| output_dir.join(name).display().to_string();
+
+        let mut targets = Vec::new();
+        if options.stdout {
+            targets.push("<stdout>".to_string());
+        } else if options.format == OutputFormat::Json {
+            targets.push(path(&format!("{}.json", self.filename)));
+        } else if options.toc_only {
+            targets.push(path("toc.txt"));
+        } else {
+            if options.combine {
+                targets.push(match options.volume_max_bytes {
+                    Some(_) => path(&format!("part_*.{}", options.format.extension())),
+                    None => path(&format!("{}.{}", self.filename, options.format.extension())),
+                });
+            }
+            if options.split {
+                targets.push(format!("{}/", path("chapters")));
+            }
+        }
+        if options.metadata && !options.stdout && !options.metadata_trailer && options.format != OutputFormat::Json {
+            targets.push(path("metadata.toml"));
+        }
+
+        let missing = self
+            .chapters
+            .iter()
+            .filter(|chapter| self.archive.index_for_name(chapter).is_none())
+            .cloned()
+            .collect();
+        OutputPlan {
+            filename: self.filename.clone(),
+            chapters: self.chapters.len(),
+            missing,
+            targets,
+        }
+    }
+
+    /// `info` 子命令的输出：metadata.toml 的内容，加上章节数、目录格式和 spine 中的章节路径
+    pub fn info(&self) -> String {
+        let mut doc = self.metadata.to_document(self.config);
+        doc["chapters"] = toml_edit::value(self.chapters.len() as i64);
+        if let Some(format) = self.toc_format {
+            doc["toc"] = toml_edit::value(match format {
+                TocFormat::Nav => "nav",
+                TocFormat::Ncx => "ncx",
+            });
+        }
+        doc["spine"] = toml_edit::value(self.chapters.iter().collect::<toml_edit::Array>());
+        doc.to_string()
+    }
+
+    /// `list` 子命令的输出：按输出顺序返回每个章节的 zip 内路径和标题
+    /// 章节的取舍和标题的选取与写入时相同，见 [`Epub::output_chapters`]
+    pub fn chapter_titles(&mut self) -> Result<Vec<(String, String)>> {
+        let chapters = self.output_chapters(&mut WriteReport::default())?;
+        Ok(chapters
+            .into_iter()
+            .map(|(index, _, chapter)| (self.chapters[index].clone(), chapter.title))
+            .collect())
+    }
+
+    /// 按 spine 顺序提取要输出的章节，JSON、文本输出和 `list` 共用，保证标题和序号一致：
+    /// EPUB3 的 nav 目录标题优先于正文中的标题，NCX 目录只补全空标题；经过后处理后丢弃被过滤的章节，
+    /// 剩余章节依次编号（与分章文件名一致），标题仍然为空时按模板以该序号合成标题
+    /// 返回 (spine 中的序号, 输出序号, 章节)，被丢弃的章节计入 `report.dropped`
+    fn output_chapters(&mut self, report: &mut WriteReport) -> Result<Vec<(usize, usize, Chapter)>> {
+        let options = &self.config.options;
+        let expected_language = if options.language_filter {
+            self.metadata.language.as_deref().and_then(language::parse_language)
+        } else {
+            None
+        };
+        let toc_titles = toc::titles(&self.toc);
+        let override_titles = self.toc_format.is_some_and(TocFormat::overrides_titles);
+        let mut chapters = TrimEmptyEdges::new(
+            ChapterIter::new(&mut self.archive, &self.chapters, self.config).enumerate(),
+            options.trim_empty_edges,
+            |(_, chapter)| chapter.as_ref().is_ok_and(|chapter| chapter.content.trim().is_empty()),
+        );
+
+        let mut kept = Vec::new();
+        for (index, chapter) in chapters.by_ref() {
+            let mut chapter = chapter?;
+            if let Some(title) = toc_titles.get(self.chapters[index].as_str())
+                && (override_titles || chapter.title.trim().is_empty())
+            {
+                chapter.title = title.to_string();
+            }
+            chapter.post_process(self.config);
+
+            if let Some(expected) = expected_language
+                && let Some(detected) = language::detect_foreign(
+                    &chapter.content,
+                    expected,
+                    options.language_confidence,
+                )
+            {
+                log::warn!(
+                    "{}: dropped chapter {} ({}): detected {} instead of {}",
+                    self.filename,
+                    index + 1,
+                    self.chapters[index],
+                    detected.code(),
+                    expected.code()
+                );
+                report.dropped += 1;
+                continue;
+            }
+
+            if options.skip_teasers
+                && postprocess::is_teaser(&chapter.content, &options.teaser_patterns, options.teaser_threshold)
+            {
+                log::warn!(
+                    "{}: dropped teaser chapter {} ({})",
+                    self.filename,
+                    index + 1,
+                    self.chapters[index]
+                );
+                report.dropped += 1;
+                continue;
+            }
+
+            if options.min_chapter_chars > 0 && chapter.content.trim().chars().count() < options.min_chapter_chars {
+                log::debug!(
+                    "{}: dropped short chapter {} ({}): {} characters",
+                    self.filename,
+                    index + 1,
+                    self.chapters[index],
+                    chapter.content.trim().chars().count()
+                );
+                report.dropped += 1;
+                continue;
+            }
+
+            if options.detect_mojibake {
+                let ratio = postprocess::mojibake_ratio(&chapter.content);
+                if ratio > options.mojibake_threshold {
+                    log::warn!(
+                        "{}: chapter {} ({}) looks like mojibake ({:.1}% suspicious characters)",
+                        self.filename,
+                        index + 1,
+                        self.chapters[index],
+                        ratio * 100.0
+                    );
+                }
+            }
+
+            kept.push((index, chapter));
+        }
+        report.dropped += chapters.trimmed();
+
+        // 只输出最长的若干章节：按后处理后的长度比较，保持 spine 顺序
+        if let Some(n) = options.top_chapters {
+            let lengths: Vec<usize> = kept.iter().map(|(_, chapter)| chapter.content.chars().count()).collect();
+            let selected = longest(&lengths, n);
+            report.dropped += kept.len() - selected.len();
+            kept = kept
+                .into_iter()
+                .enumerate()
+                .filter(|(position, _)| selected.contains(position))
+                .map(|(_, chapter)| chapter)
+                .collect();
+        }
+
+        Ok(kept
+            .into_iter()
+            .enumerate()
+            .map(|(position, (index, mut chapter))| {
+                let number = position + 1;
+                if options.synthesize_titles {
+                    chapter.synthesize_title(&options.title_template, number);
+                }
+                (index, number, chapter)
+            })
+            .collect())
+    }
+
+    /// 增量模式的判断：按当前配置应有的输出都已存在，且修改时间不早于源 EPUB
+    /// 输出到标准输出或没有源文件时总是返回 false
+    pub fn is_up_to_date(&self) -> bool {
+        let options = &self.config.options;
+        let Ok(source_mtime) = self.source_path.metadata().and_then(|metadata| metadata.modified()) else {
+            return false;
+        };
+        if options.stdout {
+            return false;
+        }
+        let output_dir = self.output_path();
+        let mut outputs = Vec::new();
+        if options.format == OutputFormat::Json {
+            outputs.push(output_dir.join(format!("{}.json", self.filename)));
+        } else if options.toc_only {
+            outputs.push(output_dir.join("toc.txt"));
+        } else {
+            if options.combine {
+                outputs.push(match options.volume_max_bytes {
+                    Some(_) => CombinedWriter::part_path(&output_dir, 1, options.format.extension()),
+                    None => output_dir.join(format!("{}.{}", self.filename, options.format.extension())),
+                });
+            }
+            if options.split {
+                outputs.push(output_dir.join("chapters"));
+            }
+        }
+        if options.metadata && !options.metadata_trailer && options.format != OutputFormat::Json {
+            outputs.push(output_dir.join("metadata.toml"));
+        }
+        !outputs.is_empty()
+            && outputs.iter().all(|path| {
+                path.metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|mtime| mtime >= source_mtime)
+            })
+    }
+
+    /// 设置了 dry_run 时不写入任何文件，只返回预期的章节数
+    pub fn write(&mut self) -> Result<WriteReport> {
+        if self.config.options.dry_run {
+            return Ok(WriteReport {
+                expected: self.chapters.len(),
+                ..WriteReport::default()
+            });
+        }
+        let options = &self.config.options;
+        let report = if options.format == OutputFormat::Json && !options.stdout {
+            self.write_json()?
+        } else {
+            self.write_outputs()?
+        };
+        if options.preserve_mtime && !options.stdout {
+            copy_mtime(&self.source_path, &self.output_dir()?)?;
+        }
+        Ok(report)
+    }
+
+    /// 元数据和全部章节写入一个 JSON 文件，元数据已包含在内，不再单独写 metadata.toml
+    fn write_json(&mut self) -> Result<WriteReport> {
+        let mut report = WriteReport {
+            expected: self.chapters.len(),
+            ..WriteReport::default()
+        };
+        let chapters: Vec<Chapter> = self
+            .output_chapters(&mut report)?
+            .into_iter()
+            .map(|(_, _, chapter)| chapter)
+            .collect();
+        report.written = chapters.len();
+        if self.config.options.stats {
+            let mut stats = TextStats::default();
+            for chapter in &chapters {
+                stats.add(&chapter.content);
+            }
+            self.write_stats(&stats, chapters.len())?;
+            report.stats = Some(stats);
+        }
+        let book = BookJson {
+            metadata: &self.metadata,
+            chapters,
+        };
+        write_atomic(&self.total_path()?, book.render()?.as_bytes())?;
+        Ok(report)
+    }
+
+    fn write_outputs(&mut self) -> Result<WriteReport> {
+        let options = &self.config.options;
+        if self.metadata.is_fixed_layout() {
+            log::warn!(
+                "{}: fixed-layout (pre-paginated) book, extracted text may be unreliable",
+                self.filename
+            );
+        }
+        // 输出到标准输出时不创建任何文件
+        let write_metadata = options.metadata && !options.stdout;
+
+        if options.toc_only {
+            return self.write_toc(write_metadata);
         }
 
-        let chapters_dir = if get_config().options.split {
+        let chapters_dir = if options.split && !options.stdout {
             Some(self.chapters_output()?)
         } else {
             None
         };
-        let mut total_file = if get_config().options.combine {
-            let total_path = self.total_path()?;
-            let mut file = File::options().append(true).open(&total_path)?;
-            if let Some(title) = &self.metadata.title {
-                writeln!(file, "{}\n", title)?;
+        let mut total_file = if options.stdout {
+            let mut writer = CombinedWriter::stdout();
+            writer.write_chunk(&format!("===== {} =====\n\n", self.filename))?;
+            Some(writer)
+        } else if options.combine {
+            Some(match options.volume_max_bytes {
+                Some(max_bytes) => CombinedWriter::volumes(
+                    self.output_dir()?,
+                    options.format.extension(),
+                    max_bytes,
+                    options.atomic_write,
+                )?,
+                None => CombinedWriter::single(&self.total_path()?, options.atomic_write)?,
             }
-            Some(file)
+            .with_encoding(options.output_encoding)
+            .with_line_ending(options.line_ending))
         } else {
             None
         };
+        if let (Some(writer), Some(title)) = (&mut total_file, &self.metadata.title) {
+            writer.write_chunk(&format!("{}\n\n", title))?;
+        }
 
-        if chapters_dir.is_none() && total_file.is_none() {
-            return Ok(());
+        // 估算阅读时间需要统计全部章节
+        // 元数据作为合并文件末尾的附录写入时，不再单独写 metadata.toml
+        let metadata_trailer = options.metadata_trailer && options.metadata && total_file.is_some();
+        let write_metadata = write_metadata && !metadata_trailer;
+        let count_text = options.stats || ((write_metadata || metadata_trailer) && options.reading_speed.is_some());
+        // 字节偏移只对不分卷、不并排的单个合并文件有意义
+        let mut provenance = (options.provenance_jsonl
+            && total_file.is_some()
+            && !options.stdout
+            && options.volume_max_bytes.is_none()
+            && !options.parallel_columns)
+            .then(Vec::new);
+        // 行号同样只对单个合并文件有意义
+        let mut toc_lines = (options.toc
+            && total_file.is_some()
+            && !options.stdout
+            && options.volume_max_bytes.is_none()
+            && !options.parallel_columns)
+            .then(Vec::new);
+        let mut unique_paragraphs = (options.unique_paragraphs && !options.stdout).then(UniqueParagraphs::default);
+        if chapters_dir.is_none() && total_file.is_none() && !count_text && unique_paragraphs.is_none() {
+            if write_metadata {
+                self.write_metadata()?;
+            }
+            return Ok(WriteReport::default());
         }
 
-        let chapters = self.get_chapters()?;
+        let mut report = WriteReport {
+            expected: self.chapters.len(),
+            ..WriteReport::default()
+        };
+        let mut columns = Vec::new();
+        let mut stats = TextStats::default();
+        let mut media = Vec::new();
+        let mut index_lines = Vec::new();
+        let part_headings = if options.include_parts && total_file.is_some() {
+            toc::part_headings(&self.toc, &self.chapters)
+        } else {
+            Vec::new()
+        };
+        let heading_depths = if options.toc_as_headings && total_file.is_some() {
+            toc::depths(&self.toc, &self.chapters)
+        } else {
+            Vec::new()
+        };
+        // 分章文件名中序号补零的位数，按 spine 中的章节数计算
+        let width = if options.pad_chapter_numbers {
+            self.chapters.len().to_string().len()
+        } else {
+            0
+        };
 
-        for (index, chapter) in chapters.enumerate() {
-            let chapter = chapter?;
+        for (index, number, mut chapter) in self.output_chapters(&mut report)? {
+            if count_text {
+                stats.add(&chapter.content);
+            }
+            if let Some(unique_paragraphs) = &mut unique_paragraphs {
+                unique_paragraphs.add(&chapter.content);
+            }
+            let chapter_path = &self.chapters[index];
+            media.extend(chapter.media.drain(..).map(|mut media| {
+                // src 相对于章节文件，解析为 zip 内的路径
+                media.src = normalize_zip_path(chapter_path, media.src);
+                (chapter_path.clone(), media)
+            }));
             if let Some(dir) = &chapters_dir {
-                chapter.write(dir, index + 1)?;
+                if options.format == OutputFormat::Obsidian {
+                    let front_matter = FrontMatter {
+                        title: &chapter.title,
+                        index: number,
+                        book: self.metadata.title.as_deref().unwrap_or(&self.filename),
+                        authors: self.metadata.authors(options.author_name),
+                        tags: &self.metadata.subjects,
+                    };
+                    chapter.write_note(dir, number, &front_matter.render(), self.config)?;
+                } else {
+                    chapter.write(dir, number, width, self.config)?;
+                }
+                if options.split_index {
+                    index_lines.push(chapter.index_line(number, width, options.format));
+                }
             }
 
             if let Some(total_file) = &mut total_file {
-                writeln!(total_file, "{}\n", chapter.title)?;
-                writeln!(total_file, "{}", chapter.content)?;
-                writeln!(total_file, "\n{}\n", &get_config().separator)?;
+                if let Some(Some(heading)) = part_headings.get(index) {
+                    if !columns.is_empty() {
+                        total_file.write_chunk(&Self::parallel_chunk(&columns, self.config))?;
+                        columns.clear();
+                    }
+                    total_file.write_chunk(&format!("{}\n\n", heading))?;
+                }
+                if options.source_boundaries && !options.parallel_columns {
+                    total_file.write_chunk(&source_boundary(&self.chapters[index]))?;
+                }
+                let heading = match &options.heading_format {
+                    Some(format) => chapter.format_heading(format, number),
+                    None => chapter.heading(options.title_underline),
+                };
+                // Markdown 格式下章节标题默认为一级标题
+                let depth = heading_depths
+                    .get(index)
+                    .copied()
+                    .or(options.format.is_markdown().then_some(1));
+                let heading = match depth {
+                    Some(depth) => {
+                        // heading_format 可能以空行开头，# 前缀加在标题文字前
+                        let text = heading.trim_start_matches('\n');
+                        let blank = &heading[..heading.len() - text.len()];
+                        format!("{}{} {}", blank, "#".repeat(depth), text)
+                    }
+                    None => heading,
+                };
+                let text = format!("{}{}", heading, chapter.content);
+                if let Some(toc_lines) = &mut toc_lines {
+                    // 标题模板可能以空行开头，行号指向标题所在的行
+                    let blank_lines = heading.len() - heading.trim_start_matches('\n').len();
+                    let line = if options.sentinels {
+                        // 标题写在开始哨兵行中
+                        total_file.line()
+                    } else {
+                        total_file.line() + blank_lines
+                    };
+                    toc_lines.push((line, chapter.title.clone()));
+                }
+                let range = if options.sentinels {
+                    Some(total_file.write_chunk(&sentinel_chunk(number, &chapter))?)
+                } else if options.parallel_columns {
+                    // 对照模式：每 parallel_stride 个章节并排输出为一组
+                    columns.push(text);
+                    if columns.len() >= options.parallel_stride {
+                        total_file.write_chunk(&Self::parallel_chunk(&columns, self.config))?;
+                        columns.clear();
+                    }
+                    None
+                } else {
+                    Some(total_file.write_text(&text, &format!("\n\n{}\n\n", &self.config.separator))?)
+                };
+                if let (Some(provenance), Some(range)) = (&mut provenance, range) {
+                    provenance.push(Provenance {
+                        index: number,
+                        source_href: self.chapters[index].clone(),
+                        title: chapter.title.clone(),
+                        char_count: chapter.content.chars().count(),
+                        byte_start: range.start,
+                        byte_end: range.end,
+                    });
+                }
             }
+            report.written += 1;
         }
 
-        Ok(())
+        if options.stats {
+            self.write_stats(&stats, report.written)?;
+            report.stats = Some(stats);
+        }
+        if count_text && let Some(speed) = &options.reading_speed {
+            let minutes = reading_minutes(speed, &stats, self.metadata.language.as_deref());
+            self.metadata.reading_time = Some(format_reading_time(minutes));
+        }
+
+        if let Some(mut total_file) = total_file {
+            if !columns.is_empty() {
+                total_file.write_chunk(&Self::parallel_chunk(&columns, self.config))?;
+            }
+            if metadata_trailer {
+                let doc = self.metadata.to_document(self.config);
+                total_file.write_chunk(&metadata_trailer_chunk(&doc.to_string()))?;
+            }
+            total_file.finish()?;
+        }
+
+        if write_metadata {
+            self.write_metadata()?;
+        }
+        if let Some(dir) = &chapters_dir
+            && options.split_index
+        {
+            let mut index = index_lines.join("\n");
+            index.push('\n');
+            write_text(&dir.join("index.txt"), &index, options)?;
+        }
+        if let Some(provenance) = provenance {
+            let path = self.total_path()?.with_extension("provenance.jsonl");
+            write_text(&path, &provenance_jsonl(&provenance)?, options)?;
+        }
+        if let Some(toc_lines) = toc_lines {
+            write_text(&self.output_dir()?.join("toc.txt"), &line_toc(&toc_lines), options)?;
+        }
+        if let Some(unique_paragraphs) = unique_paragraphs {
+            let path = self.output_dir()?.join("unique_paragraphs.txt");
+            write_text(&path, &unique_paragraphs.into_text(options.sort_paragraphs), options)?;
+        }
+        if options.media_index && !options.stdout && !media.is_empty() {
+            let path = self.output_dir()?.join("media.toml");
+            write_text(&path, &media::to_document(&media).to_string(), options)?;
+        }
+
+        if options.verify && !report.is_complete() {
+            log::warn!(
+                "{}: wrote {} of {} expected chapters (difference: {})",
+                self.filename,
+                report.written,
+                report.expected,
+                report.written as isize - report.expected as isize
+            );
+        }
+
+        Ok(report)
+    }
+
+    /// 只输出目录大纲，不提取任何章节内容
+    fn write_toc(&self, write_metadata: bool) -> Result<WriteReport> {
+        if self.toc.is_empty() {
+            log::warn!("{}: no table of contents found", self.filename);
+        }
+        let outline = toc::outline(&self.toc);
+        if self.config.options.stdout {
+            let mut writer = CombinedWriter::stdout();
+            writer.write_chunk(&format!("===== {} =====\n\n{}", self.filename, outline))?;
+            writer.finish()?;
+        } else {
+            write_text(&self.output_dir()?.join("toc.txt"), &outline, &self.config.options)?;
+        }
+        if write_metadata {
+            self.write_metadata()?;
+        }
+        Ok(WriteReport::default())
+    }
+
+    fn parallel_chunk(columns: &[String], config: &Config) -> String {
+        format!(
+            "{}\n{}\n\n",
+            parallel_rows(columns, &config.options.column_separator),
+            &config.separator
+        )
+    }
+
+    /// 只提取指定的章节，`hrefs` 与规范化后的章节路径完全匹配，或匹配其末尾的若干级路径
+    /// 返回结果按 spine 顺序排列
+    pub fn extract_hrefs(&mut self, hrefs: &[&str]) -> Result<Vec<Chapter>> {
+        let matches = |path: &str| {
+            hrefs.iter().any(|href| {
+                path == *href
+                    || path
+                        .strip_suffix(href)
+                        .is_some_and(|prefix| prefix.ends_with('/'))
+            })
+        };
+
+        self.chapters
+            .iter()
+            .filter(|path| matches(path))
+            .map(|path| Chapter::extract_chapter(&mut self.archive, path, self.config))
+            .collect()
+    }
+
+    pub fn get_chapters(&mut self) -> Result<ChapterIter<'_, R>> {
+        Ok(ChapterIter::new(&mut self.archive, &self.chapters, self.config))
+    }
+
+    /// 按 spine 顺序提取所有章节并返回，不写入任何文件，也不受输出相关的选项影响
+    pub fn into_chapters(mut self) -> Result<Vec<Chapter>> {
+        ChapterIter::new(&mut self.archive, &self.chapters, self.config).collect()
+    }
+
+    fn extract_toc(epub: &mut ZipArchive<R>, toc_path: &str, format: TocFormat) -> Result<Vec<TocEntry>> {
+        let file = epub.by_name(toc_path)?;
+        toc::parse(BufReader::new(file), format, toc_path)
+    }
+
+    /// 检查 `mimetype` 条目，返回发现的问题
+    /// 规范要求它是第一个条目、不压缩，内容为 `application/epub+zip`
+    fn check_mimetype(epub: &mut ZipArchive<R>) -> Option<String> {
+        let Some(index) = epub.index_for_name("mimetype") else {
+            return Some("mimetype entry is missing".to_string());
+        };
+        let mut file = match epub.by_index(index) {
+            Ok(file) => file,
+            Err(e) => return Some(format!("failed to read mimetype entry: {}", e)),
+        };
+        let mut content = String::new();
+        if file.read_to_string(&mut content).is_err() || content.trim() != "application/epub+zip" {
+            return Some(format!("unexpected mimetype {:?}", content.trim()));
+        }
+        if index != 0 {
+            return Some("mimetype is not the first zip entry".to_string());
+        }
+        if file.compression() != CompressionMethod::Stored {
+            return Some("mimetype entry is compressed".to_string());
+        }
+        None
+    }
+
+    /// `META-INF/encryption.xml` 中有使用字体混淆以外算法加密的资源时，认为 EPUB 受 DRM 保护
+    /// 只混淆了嵌入字体的 EPUB 仍可正常提取文本
+    fn is_drm_protected(epub: &mut ZipArchive<R>) -> Result<bool> {
+        const FONT_OBFUSCATION: [&[u8]; 2] = [b"http://www.idpf.org/2008/embedding", b"http://ns.adobe.com/pdf/enc#RC"];
+        let Ok(file) = epub.by_name("META-INF/encryption.xml") else {
+            return Ok(false);
+        };
+        let mut reader = Reader::from_reader(BufReader::new(file));
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"EncryptionMethod" => {
+                    let algorithm = e.try_get_attribute("Algorithm")?;
+                    if algorithm.is_none_or(|algorithm| !FONT_OBFUSCATION.contains(&algorithm.value.as_ref())) {
+                        return Ok(true);
+                    }
+                }
+                Event::Eof => return Ok(false),
+                _ => {}
+            }
+            buf.clear();
+        }
     }
 
-    pub fn get_chapters(&mut self) -> Result<ChapterIter<'_>> {
-        Ok(ChapterIter::new(&mut self.archive, &self.chapters))
+    fn extract_opf_path(epub: &mut ZipArchive<R>) -> Result<String> {
+        let container = epub.by_name("META-INF/container.xml")?;
+        Self::parse_container(BufReader::new(container))
     }
 
-    fn extract_opf_path(epub: &mut ZipArchive<File>) -> Result<String> {
-        let container: zip::read::ZipFile<'_, File> = epub.by_name("META-INF/container.xml")?;
-        let container = BufReader::new(container);
+    fn parse_container<B: BufRead>(container: B) -> Result<String> {
         let mut reader = Reader::from_reader(container);
         reader.config_mut().trim_text(true);
         let mut buf = Vec::new();
@@ -147,7 +905,9 @@ impl Epub {
                     for attr in e.attributes() {
                         let attr = attr?;
                         if attr.key.as_ref() == b"full-path" {
-                            return Ok(String::from_utf8(attr.value.into_owned())?);
+                            // 属性值中可能包含 `&#x20;` 之类的实体引用
+                            let path = attr.decode_and_unescape_value(reader.decoder())?;
+                            return Ok(path.into_owned());
                         }
                     }
                 }
@@ -160,3 +920,696 @@ impl Epub {
         Err(anyhow::anyhow!("OPF path not found in container.xml"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::Path;
+
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    use crate::config::{LineEnding, Options, OutputEncoding};
+
+    /// 在 `dir` 中生成一个最小的 EPUB，`chapters` 为 (href, 正文) 列表
+    fn build_epub(dir: &Path, chapters: &[(&str, &str)]) -> PathBuf {
+        let path = dir.join("book.epub");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        let options = SimpleFileOptions::default();
+
+        zip.start_file("META-INF/container.xml", options).unwrap();
+        zip.write_all(br#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#)
+            .unwrap();
+
+        let manifest: String = chapters
+            .iter()
+            .enumerate()
+            .map(|(index, (href, _))| {
+                format!(r#"<item id="c{}" href="{}" media-type="application/xhtml+xml"/>"#, index, href)
+            })
+            .collect();
+        let spine: String = (0..chapters.len())
+            .map(|index| format!(r#"<itemref idref="c{}"/>"#, index))
+            .collect();
+        zip.start_file("OEBPS/content.opf", options).unwrap();
+        write!(
+            zip,
+            r#"<package><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>测试</dc:title></metadata><manifest>{}</manifest><spine>{}</spine></package>"#,
+            manifest, spine
+        )
+        .unwrap();
+
+        for (href, body) in chapters {
+            zip.start_file(format!("OEBPS/{}", href), options).unwrap();
+            write!(zip, "<html><body>{}</body></html>", body).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    /// 在已生成的 EPUB 中加入 `META-INF/encryption.xml`，`algorithm` 为加密算法
+    fn add_encryption(path: &Path, algorithm: &str) {
+        let file = File::options().read(true).write(true).open(path).unwrap();
+        let mut zip = ZipWriter::new_append(file).unwrap();
+        zip.start_file("META-INF/encryption.xml", SimpleFileOptions::default()).unwrap();
+        write!(
+            zip,
+            r#"<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container" xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+<enc:EncryptedData><enc:EncryptionMethod Algorithm="{}"/>
+<enc:CipherData><enc:CipherReference URI="OEBPS/ch1.xhtml"/></enc:CipherData></enc:EncryptedData>
+</encryption>"#,
+            algorithm
+        )
+        .unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_check_mimetype() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<p>正文</p>")]);
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(Epub::check_mimetype(&mut archive).as_deref(), Some("mimetype entry is missing"));
+
+        let mimetype = |name: &str, content: &str, compression| {
+            let path = dir.path().join(name);
+            let mut zip = ZipWriter::new(File::create(&path).unwrap());
+            zip.start_file("mimetype", SimpleFileOptions::default().compression_method(compression))
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+            zip.finish().unwrap();
+            Epub::check_mimetype(&mut ZipArchive::new(File::open(&path).unwrap()).unwrap())
+        };
+        assert_eq!(mimetype("ok.epub", "application/epub+zip", CompressionMethod::Stored), None);
+        assert_eq!(
+            mimetype("zip.epub", "application/zip", CompressionMethod::Stored).as_deref(),
+            Some(r#"unexpected mimetype "application/zip""#)
+        );
+        assert_eq!(
+            mimetype("deflated.epub", "application/epub+zip", CompressionMethod::Deflated).as_deref(),
+            Some("mimetype entry is compressed")
+        );
+        // 默认只警告，strict_mimetype 时拒绝打开
+        assert!(Epub::from_file_with_config(path.clone(), &Config::default()).is_ok());
+        let strict = Config {
+            options: Options {
+                strict_mimetype: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let error = Epub::from_file_with_config(path, &strict).err().unwrap();
+        assert_eq!(error.to_string(), "Not a valid EPUB: mimetype entry is missing");
+    }
+
+    #[test]
+    fn test_dedup_chapters_by_resolved_path() {
+        let dir = tempfile::tempdir().unwrap();
+        // 两个 manifest 项的 href 不同，但解析后是同一个文件
+        let path = build_epub(
+            dir.path(),
+            &[("ch1.xhtml", "<p>一</p>"), ("Text/../ch1.xhtml", "<p>一</p>"), ("ch2.xhtml", "<p>二</p>")],
+        );
+
+        let config = Config::default();
+        let epub = Epub::from_file_with_config(path.clone(), &config).unwrap();
+        assert_eq!(epub.chapters, ["OEBPS/ch1.xhtml", "OEBPS/ch2.xhtml"]);
+
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            options: Options {
+                dedup_chapters: false,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        assert_eq!(epub.chapters, ["OEBPS/ch1.xhtml", "OEBPS/ch1.xhtml", "OEBPS/ch2.xhtml"]);
+        // 关闭去重时每次出现都会输出
+        epub.write().unwrap();
+        let combined = std::fs::read_to_string(epub.total_path().unwrap()).unwrap();
+        assert_eq!(combined.matches("一").count(), 2);
+    }
+
+    #[test]
+    fn test_drm_protected_epub_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<p>正文</p>")]);
+        add_encryption(&path, "http://www.w3.org/2001/04/xmlenc#aes128-cbc");
+        let config = Config::default();
+        let error = Epub::from_file_with_config(path, &config).err().unwrap();
+        assert_eq!(error.to_string(), "EPUB appears to be DRM-protected (encryption.xml present)");
+
+        // 只混淆了字体的 EPUB 不受影响，正文照常提取
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<p>正文</p>")]);
+        add_encryption(&path, "http://www.idpf.org/2008/embedding");
+        let chapters = Epub::from_file_with_config(path, &config).unwrap().into_chapters().unwrap();
+        assert_eq!(chapters[0].content, "正文\n");
+    }
+
+    #[test]
+    fn test_epub_nested_in_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let epub_path = build_epub(dir.path(), &[("ch1.xhtml", "<p>压缩包里的书</p>")]);
+
+        let bundle = dir.path().join("release.zip");
+        let mut zip = ZipWriter::new(File::create(&bundle).unwrap());
+        let options = SimpleFileOptions::default();
+        zip.start_file("release/cover.jpg", options).unwrap();
+        zip.write_all(b"jpeg").unwrap();
+        zip.start_file("release/book.epub", options).unwrap();
+        zip.write_all(&std::fs::read(&epub_path).unwrap()).unwrap();
+        zip.finish().unwrap();
+
+        let nested = crate::archive::extract_nested_epubs(&bundle).unwrap();
+        assert_eq!(nested.epubs.len(), 1);
+        assert_eq!(nested.epubs[0].1, Path::new("release/book.epub"));
+
+        let mut epub = Epub::from_file(nested.epubs[0].0.clone()).unwrap();
+        let chapters = epub.extract_hrefs(&["ch1.xhtml"]).unwrap();
+        assert_eq!(chapters[0].content, "压缩包里的书\n");
+    }
+
+    #[test]
+    fn test_plan_reports_missing_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<p>一</p>"), ("ch2.xhtml", "<p>二</p>")]);
+        let output_dir = dir.path().join("output");
+        let config = Config {
+            output_dir: output_dir.to_string_lossy().into_owned(),
+            options: Options {
+                dry_run: true,
+                split: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        epub.chapters.push("OEBPS/missing.xhtml".to_string());
+
+        let plan = epub.plan();
+        assert_eq!(plan.filename, "book");
+        assert_eq!(plan.chapters, 3);
+        assert_eq!(plan.missing, ["OEBPS/missing.xhtml"]);
+        assert!(plan.to_string().contains("⚠️ 缺少文件: OEBPS/missing.xhtml"));
+        let book_dir = output_dir.join("book");
+        assert_eq!(
+            plan.targets,
+            [
+                book_dir.join("book.txt").display().to_string(),
+                format!("{}/", book_dir.join("chapters").display()),
+                book_dir.join("metadata.toml").display().to_string(),
+            ]
+        );
+
+        // 试运行不创建任何文件，只报告预期的章节数
+        let report = epub.write().unwrap();
+        assert_eq!((report.expected, report.written), (3, 0));
+        assert!(!output_dir.exists());
+    }
+
+    #[test]
+    fn test_extract_hrefs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[
+                ("Text/ch1.xhtml", "<h1>一</h1><p>第一章</p>"),
+                ("Text/ch2.xhtml", "<h1>二</h1><p>第二章</p>"),
+                ("Text/ch3.xhtml", "<h1>三</h1><p>第三章</p>"),
+            ],
+        );
+        let mut epub = Epub::from_file(path).unwrap();
+
+        let chapters = epub
+            .extract_hrefs(&["Text/ch3.xhtml", "OEBPS/Text/ch1.xhtml", "h2.xhtml"])
+            .unwrap();
+        // 按 spine 顺序返回；"h2.xhtml" 只是 "ch2.xhtml" 的字符后缀，不算匹配
+        let titles: Vec<&str> = chapters.iter().map(|chapter| chapter.title.as_str()).collect();
+        assert_eq!(titles, ["一", "三"]);
+        assert_eq!(chapters[1].content, "第三章\n");
+
+        // 不写入任何文件
+        assert!(epub.extract_hrefs(&["missing.xhtml"]).unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_into_chapters_from_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<h1>一</h1><p>第一章</p>"), ("ch2.xhtml", "<p>第二章</p>")]);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let epub = Epub::from_reader(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(epub.metadata.title.as_deref(), Some("测试"));
+        assert_eq!(epub.into_chapters().unwrap().len(), 2);
+
+        // 输出相关的选项（丢弃短章节、分章、输出目录）不影响返回的章节，也不会写入文件
+        let output_dir = dir.path().join("output");
+        let config = Config {
+            output_dir: output_dir.to_string_lossy().into_owned(),
+            options: Options {
+                source_hash: true,
+                min_chapter_chars: 100,
+                split: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let epub = Epub::from_bytes_with_config(bytes.clone(), &config).unwrap();
+        assert_eq!(epub.metadata.source_sha256, Some(sha256_bytes(&bytes)));
+        let chapters = epub.into_chapters().unwrap();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title, "一");
+        assert_eq!(chapters[1].content, "第二章\n");
+        assert!(!output_dir.exists());
+    }
+
+    #[test]
+    fn test_books_with_different_configs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<h1>标题</h1><p>正文</p><aside>旁注</aside>")]);
+        let plain = Config::default();
+        let mut custom = Config::default();
+        custom.tags.block.insert(b"aside");
+        custom.tags.title.remove(b"h1".as_slice());
+
+        // 两本书同时打开，各自使用自己的标签配置
+        let mut first = Epub::from_file_with_config(path.clone(), &plain).unwrap();
+        let mut second = Epub::from_file_with_config(path, &custom).unwrap();
+        let chapter = first.get_chapters().unwrap().next().unwrap().unwrap();
+        assert_eq!((chapter.title.as_str(), chapter.content.as_str()), ("标题", "正文\n"));
+        let chapter = second.get_chapters().unwrap().next().unwrap().unwrap();
+        assert_eq!(chapter.title, "");
+        assert!(chapter.content.ends_with("正文\n旁注\n"));
+    }
+
+    #[test]
+    fn test_is_up_to_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<p>正文</p>")]);
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path.clone(), &config).unwrap();
+        assert!(!epub.is_up_to_date());
+        epub.write().unwrap();
+        assert!(epub.is_up_to_date());
+
+        // 按新配置应有的输出（此处为 Markdown 合并文件）不存在时同样需要转换
+        let markdown = Config {
+            output_dir: config.output_dir.clone(),
+            options: Options {
+                format: OutputFormat::Markdown,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut markdown_epub = Epub::from_file_with_config(path.clone(), &markdown).unwrap();
+        assert!(!markdown_epub.is_up_to_date());
+        markdown_epub.write().unwrap();
+        assert!(markdown_epub.is_up_to_date());
+
+        // 源文件更新后需要重新转换
+        let later = filetime::FileTime::from_unix_time(4_000_000_000, 0);
+        filetime::set_file_mtime(&path, later).unwrap();
+        assert!(!epub.is_up_to_date());
+    }
+
+    #[test]
+    fn test_min_chapter_chars_drops_short_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[
+                ("copyright.xhtml", "<p>© 2024</p>"),
+                ("ch1.xhtml", "<h1>一</h1><p>第一章的正文。</p>"),
+                ("blank.xhtml", "<p> </p>"),
+                ("ch2.xhtml", "<h1>二</h1><p>第二章的正文。</p>"),
+            ],
+        );
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            options: Options {
+                min_chapter_chars: 7,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        let report = epub.write().unwrap();
+        assert_eq!((report.written, report.dropped), (2, 2));
+
+        let mut files: Vec<String> = std::fs::read_dir(epub.chapters_output().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["chapter_1.txt", "chapter_2.txt"]);
+        // 被丢弃的章节不占用序号
+        let second = std::fs::read_to_string(epub.chapters_output().unwrap().join("chapter_2.txt")).unwrap();
+        assert_eq!(second, "二\n\n第二章的正文。\n\n");
+        let combined = std::fs::read_to_string(epub.total_path().unwrap()).unwrap();
+        assert!(!combined.contains("2024") && combined.contains("第二章的正文。"));
+    }
+
+    #[test]
+    fn test_toc_line_numbers_match_combined_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[
+                ("ch1.xhtml", "<h1>第一章</h1><p>一</p><p>二</p>"),
+                ("ch2.xhtml", "<h1>第二章</h1><p>三</p>"),
+                ("ch3.xhtml", "<h1>第三章</h1><p>四</p><p>五</p><p>六</p>"),
+            ],
+        );
+        for heading_format in [None, Some("\n\n【{index}】{title}\n".to_string())] {
+            let config = Config {
+                output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+                separator: "* * *".to_string(),
+                options: Options {
+                    toc: true,
+                    heading_format: heading_format.clone(),
+                    ..Options::default()
+                },
+                ..Config::default()
+            };
+            let mut epub = Epub::from_file_with_config(path.clone(), &config).unwrap();
+            epub.write().unwrap();
+
+            let combined = std::fs::read_to_string(epub.total_path().unwrap()).unwrap();
+            let lines: Vec<&str> = combined.lines().collect();
+            let toc = std::fs::read_to_string(epub.output_dir().unwrap().join("toc.txt")).unwrap();
+            assert_eq!(toc.lines().count(), 3);
+            // 行号计入开头的书名行和标题模板中的空行
+            let first: usize = toc.lines().next().unwrap().trim_start().split_once("  ").unwrap().0.parse().unwrap();
+            assert_eq!(first, if heading_format.is_some() { 5 } else { 3 });
+            for entry in toc.lines() {
+                let (line, title) = entry.trim_start().split_once("  ").unwrap();
+                assert!(lines[line.parse::<usize>().unwrap() - 1].contains(title));
+            }
+        }
+    }
+
+    /// 读取 UTF-16 LE 编码（带 BOM）的输出文件
+    fn read_utf16(path: &Path) -> String {
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(&bytes[..2], [0xFF, 0xFE], "{} has no UTF-16 LE BOM", path.display());
+        let units: Vec<u16> = bytes[2..].chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        String::from_utf16(&units).unwrap()
+    }
+
+    #[test]
+    fn test_utf16_side_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[("ch1.xhtml", "<h1>第一章</h1><p>一</p>"), ("ch2.xhtml", "<h1>第二章</h1><p>二</p>")],
+        );
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            options: Options {
+                output_encoding: OutputEncoding::Utf16le,
+                split: true,
+                split_index: true,
+                toc: true,
+                metadata: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        epub.write().unwrap();
+
+        let output_dir = epub.output_dir().unwrap();
+        assert!(read_utf16(&epub.total_path().unwrap()).contains("第二章"));
+        assert_eq!(read_utf16(&output_dir.join("toc.txt")).lines().count(), 2);
+        let index = read_utf16(&epub.chapters_output().unwrap().join("index.txt"));
+        assert_eq!(index.lines().next(), Some("chapter_1.txt\t第一章\t2"));
+        assert!(read_utf16(&output_dir.join("metadata.toml")).contains("测试"));
+    }
+
+    #[test]
+    fn test_crlf_side_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[("ch1.xhtml", "<h1>第一章</h1><p>一</p>"), ("ch2.xhtml", "<h1>第二章</h1><p>二</p>")],
+        );
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            options: Options {
+                line_ending: LineEnding::Crlf,
+                split: true,
+                split_index: true,
+                toc: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        epub.write().unwrap();
+
+        // 附属文件与合并文件使用相同的换行风格，不混用 LF
+        let toc = std::fs::read_to_string(epub.output_dir().unwrap().join("toc.txt")).unwrap();
+        let index = std::fs::read_to_string(epub.chapters_output().unwrap().join("index.txt")).unwrap();
+        for text in [toc, index] {
+            assert_eq!(text.matches("\r\n").count(), 2);
+            assert_eq!(text.matches('\n').count(), 2);
+        }
+    }
+
+    #[test]
+    fn test_markdown_volumes_use_md_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[("ch1.xhtml", "<h1>第一章</h1><p>一</p>"), ("ch2.xhtml", "<h1>第二章</h1><p>二</p>")],
+        );
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            options: Options {
+                format: OutputFormat::Markdown,
+                volume_max_bytes: Some(1),
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        epub.write().unwrap();
+
+        let output_dir = epub.output_dir().unwrap();
+        assert!(std::fs::read_to_string(output_dir.join("part_002.md")).unwrap().contains("# 第一章"));
+        assert!(!output_dir.join("part_001.txt").exists());
+        assert!(epub.is_up_to_date());
+    }
+
+    #[test]
+    fn test_stats_report_and_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[("ch1.xhtml", "<h1>一</h1><p>你好世界</p>"), ("ch2.xhtml", "<p>Hello there, world.</p>")],
+        );
+        for format in [OutputFormat::Text, OutputFormat::Json] {
+            let config = Config {
+                output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+                options: Options {
+                    stats: true,
+                    stats_file: true,
+                    format,
+                    ..Options::default()
+                },
+                ..Config::default()
+            };
+            let mut epub = Epub::from_file_with_config(path.clone(), &config).unwrap();
+            let report = epub.write().unwrap();
+            let stats = report.stats.unwrap();
+            assert_eq!((stats.words, stats.cjk_chars), (4 + 3, 4));
+
+            let file = std::fs::read_to_string(epub.output_dir().unwrap().join("stats.toml")).unwrap();
+            let doc: toml_edit::DocumentMut = file.parse().unwrap();
+            assert_eq!(doc["chapters"].as_integer(), Some(2));
+            assert_eq!(doc["words"].as_integer(), Some(7));
+        }
+
+        // 只统计实际输出的章节
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            options: Options {
+                stats: true,
+                min_chapter_chars: 10,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        let report = epub.write().unwrap();
+        let stats = report.stats.unwrap();
+        assert_eq!((report.written, stats.words, stats.cjk_chars), (1, 3, 0));
+    }
+
+    #[test]
+    fn test_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<p>一</p>"), ("Text/ch2.xhtml", "<p>二</p>")]);
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+
+        let info: toml_edit::DocumentMut = epub.info().parse().unwrap();
+        assert_eq!(info["title"].as_str(), Some("测试"));
+        assert_eq!(info["chapters"].as_integer(), Some(2));
+        let spine: Vec<&str> = info["spine"].as_array().unwrap().iter().filter_map(|path| path.as_str()).collect();
+        assert_eq!(spine, ["OEBPS/ch1.xhtml", "OEBPS/Text/ch2.xhtml"]);
+        // 没有目录时不输出 toc
+        assert!(info.get("toc").is_none());
+
+        epub.toc_format = Some(TocFormat::Nav);
+        let info: toml_edit::DocumentMut = epub.info().parse().unwrap();
+        assert_eq!(info["toc"].as_str(), Some("nav"));
+        // 不进行转换，也不创建输出目录
+        assert!(!dir.path().join("output").exists());
+    }
+
+    #[test]
+    fn test_chapter_titles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(dir.path(), &[("ch1.xhtml", "<h1>第一章</h1><p>一</p>"), ("ch2.xhtml", "<p>二</p>")]);
+        let config = Config {
+            options: Options {
+                synthesize_titles: false,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        assert_eq!(
+            epub.chapter_titles().unwrap(),
+            [
+                ("OEBPS/ch1.xhtml".to_string(), "第一章".to_string()),
+                ("OEBPS/ch2.xhtml".to_string(), String::new()),
+            ]
+        );
+
+        // 目录中的标题补全空标题
+        epub.toc = vec![TocEntry {
+            title: "第二章".to_string(),
+            href: "OEBPS/ch2.xhtml".to_string(),
+            ..TocEntry::default()
+        }];
+        epub.toc_format = Some(TocFormat::Ncx);
+        assert_eq!(epub.chapter_titles().unwrap()[1].1, "第二章");
+
+        // NCX 目录不覆盖正文中的标题，EPUB3 的 nav 目录则优先
+        epub.toc.push(TocEntry {
+            title: "序章".to_string(),
+            href: "OEBPS/ch1.xhtml".to_string(),
+            ..TocEntry::default()
+        });
+        assert_eq!(epub.chapter_titles().unwrap()[0].1, "第一章");
+        epub.toc_format = Some(TocFormat::Nav);
+        assert_eq!(epub.chapter_titles().unwrap()[0].1, "序章");
+        epub.toc.pop();
+
+        // 目录中也没有标题时默认按模板合成，序号与分章文件名一致
+        let config = Config::default();
+        epub.config = &config;
+        epub.toc.clear();
+        assert_eq!(epub.chapter_titles().unwrap()[1].1, "Chapter 2");
+    }
+
+    #[test]
+    fn test_fallback_titles_match_between_json_and_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[
+                ("ch1.xhtml", "<p>太短</p>"),
+                ("ch2.xhtml", "<p>第二个章节的正文足够长。</p>"),
+                ("ch3.xhtml", "<p>第三个章节的正文也足够长。</p>"),
+            ],
+        );
+        let config = |format| Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            options: Options {
+                format,
+                min_chapter_chars: 5,
+                split: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+
+        // 第一章被丢弃，第三章输出为 chapter_2.txt，标题同为 Chapter 2
+        let text_config = config(OutputFormat::Text);
+        let mut epub = Epub::from_file_with_config(path.clone(), &text_config).unwrap();
+        epub.write().unwrap();
+        let chapter = std::fs::read_to_string(epub.chapters_output().unwrap().join("chapter_2.txt")).unwrap();
+        assert!(chapter.starts_with("Chapter 2\n") && chapter.contains("第三个章节"));
+        assert_eq!(epub.chapter_titles().unwrap()[1], ("OEBPS/ch3.xhtml".to_string(), "Chapter 2".to_string()));
+
+        let json_config = config(OutputFormat::Json);
+        let mut epub = Epub::from_file_with_config(path, &json_config).unwrap();
+        epub.write().unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(epub.total_path().unwrap()).unwrap()).unwrap();
+        let titles: Vec<&str> = json["chapters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|chapter| chapter["title"].as_str())
+            .collect();
+        assert_eq!(titles, ["Chapter 1", "Chapter 2"]);
+        assert!(json["chapters"][1]["content"].as_str().unwrap().contains("第三个章节"));
+    }
+
+    #[test]
+    fn test_top_chapters_ranks_processed_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = build_epub(
+            dir.path(),
+            &[
+                ("ch1.xhtml", "<p>最新章节请访问 www.example.com 阅读，更新最快最全的小说网站</p>"),
+                ("ch2.xhtml", "<p>第二章比第一章的正文长。</p>"),
+                ("ch3.xhtml", "<p>第三章的正文最长，一定会被选中。</p>"),
+            ],
+        );
+        let config = Config {
+            output_dir: dir.path().join("output").to_string_lossy().into_owned(),
+            options: Options {
+                top_chapters: Some(2),
+                skip_teasers: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut epub = Epub::from_file_with_config(path, &config).unwrap();
+        let report = epub.write().unwrap();
+        assert_eq!((report.written, report.dropped), (2, 1));
+
+        // 最长的引流章节先被丢弃，不占用名额；保持 spine 顺序输出
+        let combined = std::fs::read_to_string(epub.total_path().unwrap()).unwrap();
+        assert!(!combined.contains("最新章节"));
+        assert!(combined.find("第二章").unwrap() < combined.find("第三章").unwrap());
+    }
+
+    #[test]
+    fn test_parse_container_decodes_references() {
+        let container = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/&#x6B;ontent&#32;&amp;.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>"#;
+        let opf_path = Epub::<File>::parse_container(container.as_bytes()).unwrap();
+        assert_eq!(opf_path, "OEBPS/kontent &.opf");
+    }
+}
@@ -1,27 +1,60 @@
+use std::collections::VecDeque;
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Seek};
 use std::path::Path;
+use std::sync::LazyLock;
 
 use anyhow::Result;
+use ego_tree::iter::Edge;
+use encoding_rs::{Encoding, UTF_8};
 use quick_xml::Reader;
 use quick_xml::events::Event;
+use regex::{Captures, Regex};
+use scraper::{Html, Node};
+use serde::Serialize;
+use unicode_width::UnicodeWidthStr;
 use zip::ZipArchive;
 
-use crate::config::get_config;
+use super::extract::Extractor;
+use super::markdown::note_file_name;
+use super::media::MediaRef;
+use super::output::TextFile;
+use super::postprocess;
+use crate::config::{Config, HtmlParser, OutputFormat};
+use crate::utils::resolve_reference;
 
-#[derive(Debug)]
+#[derive(Debug, Default, Serialize)]
 pub struct Chapter {
     pub title: String,
 
     pub content: String,
+
+    /// 按出现顺序记录的音视频引用，仅在启用 media_index 时收集
+    #[serde(skip)]
+    pub media: Vec<MediaRef>,
 }
 
 impl Chapter {
-    pub fn extract_chapter(epub: &mut ZipArchive<File>, path: &str) -> Result<Chapter> {
+    pub fn extract_chapter<R: Read + Seek>(epub: &mut ZipArchive<R>, path: &str, config: &Config) -> Result<Chapter> {
         let file = epub.by_name(path)?;
-        let reader = BufReader::new(file);
+        Self::parse(BufReader::new(file), config)
+    }
+
+    /// 从 XHTML 中提取标题和正文，非 UTF-8 的文档先按 BOM 或 XML 声明中的编码转为 UTF-8
+    pub fn parse<R: BufRead>(mut reader: R, config: &Config) -> Result<Chapter> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let bytes = to_utf8(bytes);
+        match config.options.html_parser {
+            HtmlParser::Strict => Self::parse_strict(bytes.as_slice(), config),
+            HtmlParser::Lenient => Self::parse_lenient(&bytes, config),
+        }
+    }
+
+    /// 使用 quick-xml 按 XML 解析，速度快，但格式严重错误的文档会解析失败
+    fn parse_strict<R: BufRead>(reader: R, config: &Config) -> Result<Chapter> {
         let mut reader = Reader::from_reader(reader);
-        reader.config_mut().trim_text(true);
+        // 不裁剪文本节点，行内元素两侧的空白由 Extractor 处理
         // 文本内容中是否应允许使用单独的 & 字符（不带与&成对的分号）
         reader.config_mut().allow_dangling_amp = true;
         // 自动扩展自闭合标签为开始和结束标签，会额外给end分配内存
@@ -30,78 +63,768 @@ impl Chapter {
         // check_end_names默认启用
         reader.config_mut().expand_empty_elements = true;
 
-        let mut title = String::new();
-        let mut content = String::with_capacity(800);
-        let mut stack = Vec::new();
+        let mut extractor = Extractor::new(config);
         let mut buf = Vec::with_capacity(800);
 
         loop {
             match reader.read_event_into(&mut buf)? {
                 Event::Start(e) | Event::Empty(e) => {
-                    stack.push(e.name().as_ref().to_vec());
+                    extractor.start(e.name().as_ref(), |key| {
+                        e.try_get_attribute(key)?
+                            .map(|attr| {
+                                attr.decode_and_unescape_value(reader.decoder())
+                                    .map(|value| value.into_owned())
+                            })
+                            .transpose()
+                            .map_err(Into::into)
+                    })?;
                 }
                 Event::Text(text) => {
-                    // html_content是xml10_content的别名，会自动处理实体转义，但是仅支持xml实体
-                    // unescape 可以处理更多html实体
-                    let decoded = text.html_content()?;
-
-                    if let Some(tag) = stack.last() {
-                        if get_config().tags.title.contains::<[u8]>(tag) {
-                            title = decoded.into_owned();
-                        } else if get_config().tags.inline.contains::<[u8]>(tag)
-                            || get_config().tags.block.contains::<[u8]>(tag)
-                        {
-                            content.push_str(&decoded);
-                        }
-                    }
+                    // html_content是xml10_content的别名，只负责解码和换行符规范化
+                    // 实体引用会被拆分为单独的 GeneralRef 事件
+                    extractor.text(&text.html_content()?);
                 }
-                Event::End(e) => {
-                    stack.pop();
-                    let tag_bytes = e.name();
-
-                    if get_config().tags.block.contains(tag_bytes.as_ref()) {
-                        content.push('\n');
+                Event::GeneralRef(reference) => {
+                    if let Some(decoded) = resolve_reference(&reference)? {
+                        extractor.push_text(&decoded);
                     }
                 }
+                Event::End(e) => extractor.end(e.name().as_ref()),
                 Event::Eof => break,
                 _ => {}
             }
             buf.clear();
         }
 
-        Ok(Chapter { title, content })
+        Ok(extractor.finish())
+    }
+
+    /// 使用 HTML5 解析器，容忍未闭合的标签、多余的 `<` 等错误，不会因格式问题失败
+    /// `bytes` 已由 [`Chapter::parse`] 转为 UTF-8，其中的非法字节替换为 U+FFFD
+    fn parse_lenient(bytes: &[u8], config: &Config) -> Result<Chapter> {
+        let document = Html::parse_document(&String::from_utf8_lossy(bytes));
+
+        let mut extractor = Extractor::new(config);
+        for edge in document.tree.root().traverse() {
+            match edge {
+                Edge::Open(node) => match node.value() {
+                    Node::Element(element) => {
+                        extractor.start(element.name().as_bytes(), |key| Ok(element.attr(key).map(String::from)))?;
+                    }
+                    Node::Text(text) => extractor.text(text),
+                    _ => {}
+                },
+                Edge::Close(node) => {
+                    if let Node::Element(element) = node.value() {
+                        extractor.end(element.name().as_bytes());
+                    }
+                }
+            }
+        }
+
+        Ok(extractor.finish())
+    }
+
+    /// 提取完成后的文本清理
+    pub fn post_process(&mut self, config: &Config) {
+        let options = &config.options;
+        if options.replace_nbsp || options.strip_soft_hyphens || options.strip_zero_width || options.normalize_nfc {
+            self.title = postprocess::normalize_chars(&self.title, options);
+            self.content = postprocess::normalize_chars(&self.content, options);
+        }
+        if options.strip_page_numbers {
+            self.content = postprocess::strip_page_numbers(&self.content, &options.page_number_pattern);
+        }
+        if options.format == OutputFormat::Sentences {
+            self.content = postprocess::split_sentences(&self.content);
+        }
+        if let Some(max) = options.max_blank_lines {
+            self.content = postprocess::collapse_blank_lines(&self.content, max);
+        }
+        if options.escape_output {
+            self.title = postprocess::escape_xml(&self.title, options.escape_quotes);
+            self.content = postprocess::escape_xml(&self.content, options.escape_quotes);
+        }
+    }
+
+    /// 标题为空时（例如章节编号由 CSS 生成），使用模板合成标题
+    /// 模板中的 `{index}` 会被替换为章节序号
+    pub fn synthesize_title(&mut self, template: &str, index: usize) {
+        if self.title.trim().is_empty() {
+            self.title = template.replace("{index}", &index.to_string());
+        }
+    }
+
+    /// 章节标题及其后的空行，设置了下划线字符时在标题下方添加与标题显示宽度一致的下划线
+    pub fn heading(&self, underline: Option<char>) -> String {
+        match underline {
+            Some(underline) => {
+                let width = UnicodeWidthStr::width(self.title.as_str());
+                format!("{}\n{}\n\n", self.title, underline.to_string().repeat(width))
+            }
+            None => format!("{}\n\n", self.title),
+        }
+    }
+
+    /// 第 `index` 个章节的文件名，序号补零到 `width` 位以便按文件名排序（0 表示不补零）
+    /// Obsidian 格式下以标题命名
+    pub fn file_name(&self, index: usize, width: usize, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Obsidian => note_file_name(index, &self.title),
+            _ => format!("chapter_{:0width$}.{}", index, format.extension(), width = width),
+        }
     }
 
-    pub fn write(&self, output_dir: &Path, index: usize) -> Result<()> {
-        let chapter_path = output_dir.join(format!("chapter_{}.txt", index));
-        let mut file = File::create(chapter_path)?;
+    /// 按格式字符串生成合并文件中的章节标题
+    /// 支持 `{title}`、`{index}` 以及补零或补空格的 `{index:03}`、`{index:3}`
+    pub fn format_heading(&self, format: &str, index: usize) -> String {
+        static INDEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{index(?::(0?)(\d+))?\}").unwrap());
 
-        writeln!(file, "{}\n", self.title)?;
-        writeln!(file, "{}", self.content)?;
+        let heading = INDEX.replace_all(format, |caps: &Captures| {
+            let width = caps.get(2).map_or(0, |width| width.as_str().parse().unwrap_or(0));
+            if caps.get(1).is_some_and(|zero| !zero.is_empty()) {
+                format!("{:0width$}", index, width = width)
+            } else {
+                format!("{:width$}", index, width = width)
+            }
+        });
+        heading.replace("{title}", &self.title)
+    }
+
+    pub fn write(&self, output_dir: &Path, index: usize, width: usize, config: &Config) -> Result<()> {
+        let options = &config.options;
+        let path = output_dir.join(self.file_name(index, width, options.format));
+        let file = TextFile::create_for(&path, options)?;
+        let heading = if options.format.is_markdown() {
+            format!("# {}\n\n", self.title)
+        } else {
+            self.heading(options.title_underline)
+        };
+        self.write_to(file, &heading)
+    }
+
+    /// 写入 Obsidian 笔记：front matter 之后是以 `#` 标题开头的 Markdown 正文
+    pub fn write_note(&self, output_dir: &Path, index: usize, front_matter: &str, config: &Config) -> Result<()> {
+        let path = output_dir.join(self.file_name(index, 0, OutputFormat::Obsidian));
+        let mut file = TextFile::create_for(&path, &config.options)?;
+        file.write_str(front_matter)?;
+        file.write_str(&format!("\n# {}\n\n", self.title))?;
+        file.write_str(&self.content)?;
+        file.write_str("\n")?;
+        file.finish()?;
         Ok(())
     }
+
+    fn write_to(&self, mut file: TextFile, heading: &str) -> Result<()> {
+        file.write_str(heading)?;
+        file.write_str(&self.content)?;
+        file.write_str("\n")?;
+        file.finish()?;
+        Ok(())
+    }
+
+    /// 分章索引中的一行：文件名、标题、正文字符数，以制表符分隔
+    pub fn index_line(&self, index: usize, width: usize, format: OutputFormat) -> String {
+        format!(
+            "{}\t{}\t{}",
+            self.file_name(index, width, format),
+            self.title.replace(['\t', '\n'], " "),
+            self.content.chars().count()
+        )
+    }
+}
+
+/// 按 BOM 或 XML 声明（`<?xml ... encoding="EUC-KR"?>`）中的编码将文档转为 UTF-8
+/// 没有声明、声明为 UTF-8 或无法识别的编码时原样返回；转换后保留的声明不影响解析
+fn to_utf8(bytes: Vec<u8>) -> Vec<u8> {
+    static DECLARATION: LazyLock<regex::bytes::Regex> = LazyLock::new(|| {
+        regex::bytes::Regex::new(r#"^<\?xml[^>]*?\sencoding\s*=\s*["']([A-Za-z0-9._:-]+)["']"#).unwrap()
+    });
+    let encoding = match Encoding::for_bom(&bytes) {
+        Some((encoding, _)) => Some(encoding),
+        None => DECLARATION
+            .captures(&bytes[..bytes.len().min(256)])
+            .and_then(|captures| Encoding::for_label(&captures[1])),
+    };
+    match encoding {
+        Some(encoding) if encoding != UTF_8 => encoding.decode(&bytes).0.into_owned().into_bytes(),
+        _ => bytes,
+    }
 }
 
-pub struct ChapterIter<'a> {
-    archive: &'a mut ZipArchive<File>,
+pub struct ChapterIter<'a, R: Read + Seek = File> {
+    archive: &'a mut ZipArchive<R>,
     paths: std::slice::Iter<'a, String>,
+    config: &'a Config,
 }
 
-impl<'a> ChapterIter<'a> {
-    pub fn new(archive: &'a mut ZipArchive<File>, paths: &'a [String]) -> Self {
+impl<'a, R: Read + Seek> ChapterIter<'a, R> {
+    pub fn new(archive: &'a mut ZipArchive<R>, paths: &'a [String], config: &'a Config) -> Self {
         Self {
             archive,
             paths: paths.iter(),
+            config,
         }
     }
 }
 
-impl<'a> Iterator for ChapterIter<'a> {
+impl<R: Read + Seek> Iterator for ChapterIter<'_, R> {
     type Item = Result<Chapter>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.paths
             .next()
-            .map(|path| Chapter::extract_chapter(self.archive, path))
+            .map(|path| Chapter::extract_chapter(self.archive, path, self.config))
+    }
+}
+
+/// 丢弃开头和结尾连续的空章节（如空白的扉页、封底），中间的空章节原样保留
+/// `enabled` 为 false 时不做任何处理
+pub struct TrimEmptyEdges<I: Iterator, F> {
+    inner: I,
+    is_empty: F,
+    enabled: bool,
+    started: bool,
+    // 遇到非空章节前暂存的空章节，之后若没有非空章节则被丢弃
+    held: Vec<I::Item>,
+    ready: VecDeque<I::Item>,
+    trimmed: usize,
+}
+
+impl<I: Iterator, F: Fn(&I::Item) -> bool> TrimEmptyEdges<I, F> {
+    pub fn new(inner: I, enabled: bool, is_empty: F) -> Self {
+        Self {
+            inner,
+            is_empty,
+            enabled,
+            started: false,
+            held: Vec::new(),
+            ready: VecDeque::new(),
+            trimmed: 0,
+        }
+    }
+
+    /// 被丢弃的章节数，迭代结束后才是最终结果
+    pub fn trimmed(&self) -> usize {
+        self.trimmed
+    }
+}
+
+impl<I: Iterator, F: Fn(&I::Item) -> bool> Iterator for TrimEmptyEdges<I, F> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.enabled {
+            return self.inner.next();
+        }
+        if let Some(item) = self.ready.pop_front() {
+            return Some(item);
+        }
+        loop {
+            let Some(item) = self.inner.next() else {
+                self.trimmed += self.held.len();
+                self.held.clear();
+                return None;
+            };
+            if (self.is_empty)(&item) {
+                if self.started {
+                    self.held.push(item);
+                } else {
+                    self.trimmed += 1;
+                }
+                continue;
+            }
+            self.started = true;
+            self.ready.extend(self.held.drain(..));
+            self.ready.push_back(item);
+            return self.ready.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Options, OutputEncoding, RubyMode};
+
+    #[test]
+    fn test_anchor_markers() {
+        let config = Config {
+            options: Options {
+                anchor_markers: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let xhtml = r#"<html><head><title id="head-title">书名</title><link id="css" rel="stylesheet" href="a.css"/></head><body>
+<h1 id="top">标题</h1>
+<p>第一段</p>
+<script id="script">var x = "<p id='fake'>";</script>
+<style id="style">p { margin: 0; }</style>
+<p id="sec&#x32;">第二段<span id="note">注</span></p>
+</body></html>"#;
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(chapter.title, "标题");
+        // 标题、<head> 和被跳过的元素中的 id 不产生标记
+        assert_eq!(chapter.content, "第一段\n[[id:sec2]]第二段[[id:note]]注\n");
+    }
+
+    #[test]
+    fn test_nested_emphasis_markers() {
+        let config = Config {
+            options: Options {
+                emphasis_markers: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let xhtml = "<html><body>
+<p>前<strong>粗<em>粗斜</em></strong>后</p>
+<p><strong>未闭合<em>强调</p>
+<p>下一段</em></strong></p>
+<p><strong>交叉<em>嵌套</strong>斜体</em><em></em></p>
+</body></html>";
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(
+            chapter.content,
+            "前**粗*粗斜***后\n**未闭合*强调***\n下一段\n**交叉*嵌套****斜体*\n"
+        );
+    }
+
+    #[test]
+    fn test_unwrap_hard_wrapped_paragraph() {
+        let config = Config {
+            options: Options {
+                unwrap_lines: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let xhtml = "<html><body>
+<p>This is a hard
+   wrapped paragraph<br/>that continues.</p>
+<p>第一行<br/>第二行
+第三行</p>
+</body></html>";
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(
+            chapter.content,
+            "This is a hard wrapped paragraph that continues.\n第一行第二行第三行\n"
+        );
+    }
+
+    #[test]
+    fn test_collapse_spaces_outside_pre() {
+        let config = Config {
+            options: Options {
+                collapse_spaces: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let xhtml = "<html><body>
+<p>Lots\tof \t  spaces\there.\nNext   line</p>
+<pre><code>keep\t\tthis    alignment</code></pre>
+</body></html>";
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(
+            chapter.content,
+            "Lots of spaces here.\nNext line\nkeep\t\tthis    alignment\n"
+        );
+    }
+
+    #[test]
+    fn test_pre_is_verbatim() {
+        let xhtml = "<html><body>
+<p>Example:</p>
+<pre>
+fn main() {
+    let  x = 1;
+
+    println!(\"{}\", x);
+}
+</pre>
+<p>After.</p>
+</body></html>";
+        let code = "fn main() {\n    let  x = 1;\n\n    println!(\"{}\", x);\n}\n";
+        for html_parser in [HtmlParser::Strict, HtmlParser::Lenient] {
+            let config = Config {
+                options: Options {
+                    html_parser,
+                    unwrap_lines: true,
+                    collapse_spaces: true,
+                    ..Options::default()
+                },
+                ..Config::default()
+            };
+            let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+            assert_eq!(chapter.content, format!("Example:\n{}After.\n", code));
+        }
+    }
+
+    #[test]
+    fn test_declared_encoding_is_transcoded() {
+        let cases = [
+            (encoding_rs::EUC_KR, r#"<?xml version="1.0" encoding="EUC-KR"?>"#, "제1장", "한국어 본문입니다."),
+            (encoding_rs::SHIFT_JIS, "<?xml version='1.0' encoding='Shift_JIS'?>", "第一章", "日本語の本文です。"),
+        ];
+        for (encoding, declaration, title, body) in cases {
+            let xhtml = format!("{}<html><body><h1>{}</h1><p>{}</p></body></html>", declaration, title, body);
+            let (bytes, _, _) = encoding.encode(&xhtml);
+            assert!(std::str::from_utf8(&bytes).is_err());
+
+            for html_parser in [HtmlParser::Strict, HtmlParser::Lenient] {
+                let config = Config {
+                    options: Options {
+                        html_parser,
+                        ..Options::default()
+                    },
+                    ..Config::default()
+                };
+                let chapter = Chapter::parse(&*bytes, &config).unwrap();
+                assert_eq!(chapter.title, title);
+                assert_eq!(chapter.content, format!("{}\n", body));
+            }
+        }
+
+        // 没有声明时按 UTF-8 处理
+        let chapter = Chapter::parse("<html><body><p>正文</p></body></html>".as_bytes(), &Config::default()).unwrap();
+        assert_eq!(chapter.content, "正文\n");
+    }
+
+    #[test]
+    fn test_words_stay_separated_across_inline_tags() {
+        let xhtml = "<html><body>
+<p>hello <a href=\"#w\">world</a>, said <em>the <strong>big</strong> </em><span>dog</span>.</p>
+<p><a href=\"#1\">one</a> <a href=\"#2\">two</a><a href=\"#3\">three</a>!</p>
+<p>AT&amp;T and <span> </span>spaced &amp; <span>joined</span>&#33;</p>
+<p>中文
+<span>换行</span>不加空格</p>
+</body></html>";
+        let expected = "hello world, said the big dog.\none twothree!\nAT&T and spaced & joined!\n中文换行不加空格\n";
+        for html_parser in [HtmlParser::Strict, HtmlParser::Lenient] {
+            let config = Config {
+                options: Options {
+                    html_parser,
+                    ..Options::default()
+                },
+                ..Config::default()
+            };
+            let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+            assert_eq!(chapter.content, expected, "{:?}", html_parser);
+        }
+
+        let config = Config {
+            options: Options {
+                emphasis_markers: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let chapter = Chapter::parse("<p>a <em>b</em> c<em> d </em>e</p>".as_bytes(), &config).unwrap();
+        assert_eq!(chapter.content, "a *b* c *d* e\n");
+    }
+
+    #[test]
+    fn test_br_marker() {
+        let config = Config {
+            options: Options {
+                br_marker: Some("\\\n".to_string()),
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let xhtml = "<html><body><p>第一行<br/>第二行<br/>第三行</p><p>下一段</p></body></html>";
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(chapter.content, "第一行\\\n第二行\\\n第三行\n下一段\n");
+    }
+
+    #[test]
+    fn test_lenient_parser_handles_tag_soup() {
+        let xhtml = "<html><head><title>乱码标签</title></head><body>
+<p id=intro>价格 < 100 元
+<p>未闭合的段落<span>和行内元素
+<div>最后一段</div>
+</body>";
+        let strict = Config {
+            options: Options {
+                anchor_markers: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        assert!(Chapter::parse(xhtml.as_bytes(), &strict).is_err());
+
+        let lenient = Config {
+            options: Options {
+                html_parser: HtmlParser::Lenient,
+                ..strict.options
+            },
+            ..Config::default()
+        };
+        let chapter = Chapter::parse(xhtml.as_bytes(), &lenient).unwrap();
+        assert_eq!(chapter.title, "乱码标签");
+        assert_eq!(
+            chapter.content,
+            "[[id:intro]]价格 < 100 元\n未闭合的段落和行内元素\n最后一段\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_tags_discard_nested_text() {
+        let xhtml = r#"<html><body><div>
+<style type="text/css"><p>p { color: red; }</p></style>
+<p>正文</p>
+<script><div><span>alert(1)</span></div></script>
+<div>结尾</div>
+</div></body></html>"#;
+        for html_parser in [HtmlParser::Strict, HtmlParser::Lenient] {
+            let config = Config {
+                options: Options {
+                    html_parser,
+                    ..Options::default()
+                },
+                ..Config::default()
+            };
+            let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+            assert!(!chapter.content.contains("color") && !chapter.content.contains("alert"));
+            assert!(chapter.content.contains("正文") && chapter.content.contains("结尾"));
+        }
+    }
+
+    #[test]
+    fn test_ruby_annotations() {
+        let xhtml = "<html><body>
+<h1><ruby>漢字<rt>かんじ</rt></ruby>の本</h1>
+<p><ruby>漢<rp>(</rp><rt>かん</rt><rp>)</rp>字<rp>(</rp><rt>じ</rt><rp>)</rp></ruby>を<ruby><rb>読</rb><rt>よ</rt></ruby>む</p>
+<p><ruby>韓國<rt>한국</rt></ruby>語</p>
+</body></html>";
+        let config = Config::default();
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(chapter.title, "漢字の本");
+        assert_eq!(chapter.content, "漢字を読む\n韓國語\n");
+
+        let config = Config {
+            options: Options {
+                ruby_mode: RubyMode::Parentheses,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(chapter.title, "漢字(かんじ)の本");
+        assert_eq!(chapter.content, "漢(かん)字(じ)を読(よ)む\n韓國(한국)語\n");
+    }
+
+    #[test]
+    fn test_endnotes() {
+        let xhtml = r##"<html xmlns:epub="http://www.idpf.org/2007/ops"><body>
+<p>First<a epub:type="noteref" href="#n2">2</a> and second<a epub:type="noteref" href="#n1">1</a> claims.</p>
+<aside epub:type="footnote" id="n1"><p>Note one.</p></aside>
+<p>Again<a epub:type="noteref" href="#n2">2</a>.</p>
+<aside epub:type="footnote" id="n2"><p>Note <em>two</em>.</p></aside>
+<aside epub:type="footnote" id="n3"><p>Unreferenced.</p></aside>
+</body></html>"##;
+        for html_parser in [HtmlParser::Strict, HtmlParser::Lenient] {
+            let config = Config {
+                options: Options {
+                    endnotes: true,
+                    html_parser,
+                    ..Options::default()
+                },
+                ..Config::default()
+            };
+            let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+            assert_eq!(
+                chapter.content,
+                "First[1] and second[2] claims.\nAgain[1].\n\n---\n\n[1] Note two.\n\n[2] Note one.\n\n[3] Unreferenced.\n"
+            );
+        }
+
+        let config = Config {
+            options: Options {
+                endnotes: true,
+                format: OutputFormat::Markdown,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert!(chapter.content.starts_with("First[^1] and second[^2] claims."));
+        assert!(chapter.content.contains("[^1]: Note *two*.\n\n[^2]: Note one.\n"));
+    }
+
+    #[test]
+    fn test_image_placeholders() {
+        let xhtml = r#"<html><body>
+<p>Before the map<img src="map.png" alt="Map of the valley"/> and <em>after</em>.</p>
+<div><img src="scene.jpg"/></div>
+<p>End.</p>
+</body></html>"#;
+        let config = Config {
+            options: Options {
+                image_placeholders: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(
+            chapter.content,
+            "Before the map\n[image: Map of the valley]\nand after.\n[image]\nEnd.\n"
+        );
+
+        let chapter = Chapter::parse(xhtml.as_bytes(), &Config::default()).unwrap();
+        assert!(!chapter.content.contains("[image"));
+    }
+
+    #[test]
+    fn test_list_markers() {
+        let xhtml = r#"<html><body>
+<p>Steps:</p>
+<ol start="3"><li>Mix</li><li>Bake<ul><li>at 180°</li><li>for 20 minutes</li></ul></li><li>Serve</li></ol>
+<ul><li>Done</li></ul>
+</body></html>"#;
+        let config = Config {
+            options: Options {
+                list_markers: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(
+            chapter.content,
+            "Steps:\n3. Mix\n4. Bake\n  - at 180°\n  - for 20 minutes\n5. Serve\n- Done\n"
+        );
+
+        let chapter = Chapter::parse(xhtml.as_bytes(), &Config::default()).unwrap();
+        assert!(!chapter.content.contains("3. ") && !chapter.content.contains("- "));
+    }
+
+    #[test]
+    fn test_scene_break() {
+        let xhtml = "<html><body><p>第一幕结束。</p><hr/><p>第二幕开始。</p></body></html>";
+        let mut chapter = Chapter::parse(xhtml.as_bytes(), &Config::default()).unwrap();
+        chapter.post_process(&Config::default());
+        assert_eq!(chapter.content, "第一幕结束。\n\n* * *\n\n第二幕开始。\n");
+
+        let config = Config {
+            options: Options {
+                scene_break: String::new(),
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(chapter.content, "第一幕结束。\n第二幕开始。\n");
+    }
+
+    #[test]
+    fn test_heading_underline_matches_display_width() {
+        let chapter = Chapter {
+            title: "第1章 始まり".to_string(),
+            ..Chapter::default()
+        };
+        assert_eq!(chapter.heading(None), "第1章 始まり\n\n");
+        // 全角字符宽度为 2：第、章、始、ま、り 共 10，加上 "1" 和空格
+        assert_eq!(chapter.heading(Some('=')), format!("第1章 始まり\n{}\n\n", "=".repeat(12)));
+    }
+
+    #[test]
+    fn test_index_counts_match_written_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let chapters = [
+            Chapter {
+                title: "第一章\t开始".to_string(),
+                content: "正文内容\n第二段\n".to_string(),
+                ..Chapter::default()
+            },
+            Chapter {
+                title: "Empty".to_string(),
+                ..Chapter::default()
+            },
+        ];
+
+        for (index, chapter) in chapters.iter().enumerate() {
+            let path = dir.path().join(chapter.file_name(index + 1, 0, OutputFormat::Text));
+            let file = TextFile::create(&path, OutputEncoding::Utf8).unwrap();
+            chapter.write_to(file, &chapter.heading(None)).unwrap();
+            let line = chapter.index_line(index + 1, 0, OutputFormat::Text);
+            let columns: Vec<&str> = line.split('\t').collect();
+            assert_eq!(columns.len(), 3);
+
+            // 写入的文件为 "标题\n\n正文\n"
+            let written = std::fs::read_to_string(dir.path().join(columns[0])).unwrap();
+            let body = written.split_once("\n\n").unwrap().1.strip_suffix('\n').unwrap();
+            assert_eq!(columns[2], body.chars().count().to_string());
+        }
+        assert_eq!(chapters[0].index_line(1, 0, OutputFormat::Text), "chapter_1.txt\t第一章 开始\t9");
+        assert_eq!(chapters[1].index_line(2, 0, OutputFormat::Text), "chapter_2.txt\tEmpty\t0");
+    }
+
+    #[test]
+    fn test_padded_file_names_sort_in_order() {
+        let chapter = Chapter::default();
+        let width = 120.to_string().len();
+        let mut names: Vec<String> = [1, 2, 10, 11, 100, 120]
+            .iter()
+            .map(|&index| chapter.file_name(index, width, OutputFormat::Text))
+            .collect();
+        assert_eq!(names[0], "chapter_001.txt");
+        assert_eq!(names[5], "chapter_120.txt");
+        let unsorted = names.clone();
+        names.sort();
+        assert_eq!(names, unsorted);
+    }
+
+    #[test]
+    fn test_format_heading() {
+        let chapter = Chapter {
+            title: "重逢".to_string(),
+            ..Chapter::default()
+        };
+        assert_eq!(chapter.format_heading("\n\n【{index}】{title}\n", 7), "\n\n【7】重逢\n");
+        assert_eq!(chapter.format_heading("{index:03}. {title}\n\n", 7), "007. 重逢\n\n");
+        assert_eq!(chapter.format_heading("[{index:3}] {title}", 12), "[ 12] 重逢");
+        // 标题中的占位符不会被再次替换
+        let chapter = Chapter {
+            title: "{index}".to_string(),
+            ..Chapter::default()
+        };
+        assert_eq!(chapter.format_heading("{index}: {title}", 1), "1: {index}");
+    }
+
+    #[test]
+    fn test_synthesize_title_only_when_empty() {
+        let mut empty = Chapter {
+            title: " ".to_string(),
+            content: "content".to_string(),
+            ..Chapter::default()
+        };
+        empty.synthesize_title("第{index}章", 3);
+        assert_eq!(empty.title, "第3章");
+
+        let mut titled = Chapter {
+            title: "序章".to_string(),
+            content: "content".to_string(),
+            ..Chapter::default()
+        };
+        titled.synthesize_title("第{index}章", 1);
+        assert_eq!(titled.title, "序章");
+    }
+
+    #[test]
+    fn test_trim_empty_edges_keeps_inner_empty_chapters() {
+        let chapters = ["", " \n", "序章", "", "第一章", "\n", ""];
+        let mut trimmed = TrimEmptyEdges::new(chapters.into_iter(), true, |content: &&str| {
+            content.trim().is_empty()
+        });
+        let kept: Vec<&str> = trimmed.by_ref().collect();
+        assert_eq!(kept, ["序章", "", "第一章"]);
+        assert_eq!(trimmed.trimmed(), 4);
+
+        let untouched: Vec<&str> = TrimEmptyEdges::new(chapters.into_iter(), false, |_: &&str| true).collect();
+        assert_eq!(untouched, chapters);
     }
 }
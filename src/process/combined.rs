@@ -0,0 +1,531 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use encoding_rs::Encoding;
+use serde::Serialize;
+
+use super::chapter::Chapter;
+use super::output::TextFile;
+use crate::config::{LineEnding, OutputEncoding};
+
+/// 合并输出的写入目标
+enum Sink {
+    File(TextFile),
+    /// 先缓存整本书，结束时一次性写入标准输出，避免并行处理时多本书的内容交错
+    Stdout(Vec<u8>),
+}
+
+impl Sink {
+    /// 返回写入的字节数
+    fn write_str(&mut self, text: &str) -> io::Result<usize> {
+        match self {
+            Sink::File(file) => file.write_str(text),
+            Sink::Stdout(buffer) => {
+                buffer.write_all(text.as_bytes())?;
+                Ok(text.len())
+            }
+        }
+    }
+}
+
+/// 合并输出的写入器
+/// 设置了分卷大小时，按 `part_001.txt`、`part_002.txt`（扩展名随输出格式）滚动写入，且不会把一个章节拆到两个分卷中
+pub struct CombinedWriter {
+    sink: Sink,
+    encoding: OutputEncoding,
+    line_ending: LineEnding,
+    output_dir: PathBuf,
+    // 分卷文件的扩展名
+    extension: &'static str,
+    max_bytes: Option<usize>,
+    // 分卷文件是否原子写入
+    atomic: bool,
+    part: usize,
+    written: usize,
+    // 当前文件中已写入的换行数
+    lines: usize,
+}
+
+impl CombinedWriter {
+    /// 不分卷，全部写入同一个文件，已有的文件会被清空，重复运行不会累积旧内容
+    /// `atomic` 时先写入临时文件，[`CombinedWriter::finish`] 时才替换目标文件
+    pub fn single(path: &Path, atomic: bool) -> Result<Self> {
+        Ok(Self {
+            sink: Sink::File(Self::create_file(path, atomic)?),
+            encoding: OutputEncoding::Utf8,
+            line_ending: LineEnding::Lf,
+            output_dir: PathBuf::new(),
+            extension: "txt",
+            max_bytes: None,
+            atomic,
+            part: 1,
+            written: 0,
+            lines: 0,
+        })
+    }
+
+    /// 分卷写入，每个分卷不超过 `max_bytes` 字节（单个章节本身超出时独占一个分卷）
+    /// `atomic` 时每个分卷写完（切换到下一个分卷或结束）后才出现
+    pub fn volumes(output_dir: PathBuf, extension: &'static str, max_bytes: usize, atomic: bool) -> Result<Self> {
+        let file = Self::create_file(&Self::part_path(&output_dir, 1, extension), atomic)?;
+        Ok(Self {
+            sink: Sink::File(file),
+            encoding: OutputEncoding::Utf8,
+            line_ending: LineEnding::Lf,
+            output_dir,
+            extension,
+            max_bytes: Some(max_bytes),
+            atomic,
+            part: 1,
+            written: 0,
+            lines: 0,
+        })
+    }
+
+    /// 写入标准输出，调用 [`CombinedWriter::finish`] 时才真正输出
+    pub fn stdout() -> Self {
+        Self {
+            sink: Sink::Stdout(Vec::new()),
+            encoding: OutputEncoding::Utf8,
+            line_ending: LineEnding::Lf,
+            output_dir: PathBuf::new(),
+            extension: "txt",
+            max_bytes: None,
+            atomic: false,
+            part: 1,
+            written: 0,
+            lines: 0,
+        }
+    }
+
+    fn create_file(path: &Path, atomic: bool) -> io::Result<TextFile> {
+        if atomic {
+            TextFile::create_atomic(path, OutputEncoding::Utf8)
+        } else {
+            TextFile::create(path, OutputEncoding::Utf8)
+        }
+    }
+
+    /// 设置写入文件时使用的编码，标准输出始终为 UTF-8
+    pub fn with_encoding(mut self, encoding: OutputEncoding) -> Self {
+        self.encoding = encoding;
+        if let Sink::File(file) = &mut self.sink {
+            file.set_encoding(encoding);
+        }
+        self
+    }
+
+    /// 设置写入文件时使用的换行风格，标准输出始终为 `\n`
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        if let Sink::File(file) = &mut self.sink {
+            file.set_line_ending(line_ending);
+        }
+        self
+    }
+
+    /// 文本写入当前文件后的字节数
+    fn encoded_len(&self, text: &str) -> usize {
+        self.encoding.encoded_len(&self.line_ending.apply(text))
+    }
+
+    pub fn part_path(output_dir: &Path, part: usize, extension: &str) -> PathBuf {
+        output_dir.join(format!("part_{:03}.{}", part, extension))
+    }
+
+    /// 写入一个完整的块（书名或一个章节），必要时先切换到新的分卷
+    /// 返回这个块在当前文件中的字节范围
+    pub fn write_chunk(&mut self, chunk: &str) -> Result<Range<usize>> {
+        if let Some(max_bytes) = self.max_bytes
+            && self.written > 0
+            && self.written + self.encoded_len(chunk) > max_bytes
+        {
+            self.part += 1;
+            let mut file = Self::create_file(&Self::part_path(&self.output_dir, self.part, self.extension), self.atomic)?;
+            file.set_encoding(self.encoding);
+            let previous = std::mem::replace(&mut self.sink, Sink::File(file.with_line_ending(self.line_ending)));
+            if let Sink::File(previous) = previous {
+                previous.finish()?;
+            }
+            self.written = 0;
+            self.lines = 0;
+        }
+
+        self.written += self.sink.write_str(chunk)?;
+        self.lines += chunk.matches('\n').count();
+        // 文件开头的 BOM 不属于这个块
+        let start = self.written - self.encoded_len(chunk);
+        Ok(start..self.written)
+    }
+
+    /// 下一个块在当前文件中开始的行号（从 1 开始）
+    pub fn line(&self) -> usize {
+        self.lines + 1
+    }
+
+    /// 将章节文本和其后的分隔内容作为一个块写入，返回章节文本本身的字节范围
+    pub fn write_text(&mut self, text: &str, trailer: &str) -> Result<Range<usize>> {
+        let range = self.write_chunk(&format!("{}{}", text, trailer))?;
+        Ok(range.start..range.start + self.encoded_len(text))
+    }
+
+    /// 写完后必须调用：刷新文件缓冲区，或将缓存的内容输出到标准输出
+    pub fn finish(self) -> Result<()> {
+        match self.sink {
+            Sink::File(file) => file.finish()?,
+            Sink::Stdout(buffer) => {
+                // StdoutLock 本身就是互斥锁，持有期间其他线程无法写入标准输出
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(&buffer)?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 将多个章节按行并排，每行各列之间以 `separator` 分隔，行数不足的列留空
+pub fn parallel_rows(columns: &[String], separator: &str) -> String {
+    let columns: Vec<Vec<&str>> = columns.iter().map(|column| column.lines().collect()).collect();
+    let rows = columns.iter().map(Vec::len).max().unwrap_or(0);
+
+    (0..rows)
+        .map(|row| {
+            columns
+                .iter()
+                .map(|column| column.get(row).copied().unwrap_or(""))
+                .collect::<Vec<_>>()
+                .join(separator)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 标记来源 XHTML 文件开始位置的注释行，与章节标题区分
+pub fn source_boundary(path: &str) -> String {
+    format!("# --- {} ---\n\n", path)
+}
+
+/// 附在合并文件末尾的元数据块，`metadata` 为 metadata.toml 的内容
+pub fn metadata_trailer_chunk(metadata: &str) -> String {
+    format!(
+        "===== METADATA =====\n{}\n===== END METADATA =====\n",
+        metadata.trim_end()
+    )
+}
+
+/// 哨兵行的前缀，正文中以此开头的行写入时会多加一个 `<` 转义
+const SENTINEL_PREFIX: &str = "<<<";
+
+/// 合并文件中一个章节的来源和位置，写入 `.provenance.jsonl` 的一行
+#[derive(Debug, Serialize)]
+pub struct Provenance {
+    pub index: usize,
+    pub source_href: String,
+    pub title: String,
+    pub char_count: usize,
+    pub byte_start: usize,
+    pub byte_end: usize,
+}
+
+/// 每个章节一行 JSON
+pub fn provenance_jsonl(records: &[Provenance]) -> Result<String> {
+    let mut jsonl = String::new();
+    for record in records {
+        jsonl.push_str(&serde_json::to_string(record)?);
+        jsonl.push('\n');
+    }
+    Ok(jsonl)
+}
+
+/// 用起止哨兵行包裹一个章节，可通过 [`split_combined`] 无损地还原
+/// 例如 `<<<BEGIN CHAPTER 001: 标题>>>` … `<<<END CHAPTER 001>>>`
+pub fn sentinel_chunk(number: usize, chapter: &Chapter) -> String {
+    let mut chunk = format!(
+        "{}BEGIN CHAPTER {:03}: {}>>>\n",
+        SENTINEL_PREFIX,
+        number,
+        chapter.title.replace('\n', " ")
+    );
+    for line in chapter.content.split_inclusive('\n') {
+        if line.starts_with(SENTINEL_PREFIX) {
+            chunk.push('<');
+        }
+        chunk.push_str(line);
+    }
+    // 正文后总是多写一个换行，还原时去掉，保证不以换行结尾的正文也能原样还原
+    chunk.push('\n');
+    chunk.push_str(&format!("{}END CHAPTER {:03}>>>\n\n", SENTINEL_PREFIX, number));
+    chunk
+}
+
+/// toc.txt 的内容：每个章节一行，右对齐的起始行号之后是标题
+pub fn line_toc(entries: &[(usize, String)]) -> String {
+    let width = entries.iter().map(|(line, _)| line.to_string().len()).max().unwrap_or(0);
+    entries
+        .iter()
+        .map(|(line, title)| format!("{:>width$}  {}\n", line, title.replace('\n', " "), width = width))
+        .collect()
+}
+
+/// 将带哨兵行的合并文件拆分回章节，哨兵之外的内容（如书名）会被忽略
+pub fn split_combined(path: &Path) -> Result<Vec<Chapter>> {
+    let bytes = std::fs::read(path)?;
+    // utf-16le 输出的文件以 BOM 开头，按 BOM 解码
+    let text = match Encoding::for_bom(&bytes) {
+        Some((encoding, bom_len)) => encoding.decode_without_bom_handling(&bytes[bom_len..]).0,
+        None => Cow::Borrowed(std::str::from_utf8(&bytes)?),
+    };
+    parse_sentinels(&text)
+}
+
+fn parse_sentinels(text: &str) -> Result<Vec<Chapter>> {
+    let mut chapters = Vec::new();
+    // (章节序号, 标题, 正文)
+    let mut current: Option<(String, String, String)> = None;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+        if let Some(begin) = trimmed
+            .strip_prefix("<<<BEGIN CHAPTER ")
+            .and_then(|rest| rest.strip_suffix(">>>"))
+        {
+            if current.is_some() {
+                bail!("Unterminated chapter before \"{}\"", trimmed);
+            }
+            let (number, title) = begin.split_once(": ").unwrap_or((begin, ""));
+            current = Some((number.to_string(), title.to_string(), String::new()));
+        } else if let Some(number) = trimmed
+            .strip_prefix("<<<END CHAPTER ")
+            .and_then(|rest| rest.strip_suffix(">>>"))
+        {
+            match current.take() {
+                Some((begin, title, mut content)) if begin == number => {
+                    content.pop();
+                    chapters.push(Chapter {
+                        title,
+                        content,
+                        ..Chapter::default()
+                    });
+                }
+                _ => bail!("Unexpected \"{}\"", trimmed),
+            }
+        } else if let Some((.., content)) = &mut current {
+            // 去掉写入时为转义加上的 `<`
+            let line = line
+                .strip_prefix('<')
+                .filter(|rest| rest.starts_with(SENTINEL_PREFIX))
+                .unwrap_or(line);
+            // crlf 输出时写入的 `\r\n` 还原为 `\n`
+            match line.strip_suffix("\r\n") {
+                Some(line) => {
+                    content.push_str(line);
+                    content.push('\n');
+                }
+                None => content.push_str(line),
+            }
+        }
+    }
+
+    if let Some((number, ..)) = current {
+        bail!("Chapter {} is missing its end sentinel", number);
+    }
+    Ok(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_rows_pairs_alternating_chapters() {
+        let chapters = ["Hello", "你好", "Good night\nSleep well", "晚安"].map(String::from);
+        let rows: Vec<String> = chapters
+            .chunks(2)
+            .map(|pair| parallel_rows(pair, "\t"))
+            .collect();
+        assert_eq!(rows, ["Hello\t你好", "Good night\t晚安\nSleep well\t"]);
+    }
+
+    #[test]
+    fn test_volumes_never_split_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        let chapters = ["a".repeat(40), "b".repeat(30), "c".repeat(50), "d".repeat(120), "e".repeat(10)];
+
+        let mut writer = CombinedWriter::volumes(dir.path().to_path_buf(), "txt", 100, false).unwrap();
+        for chapter in &chapters {
+            writer.write_chunk(chapter).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let parts: Vec<String> = (1..=4)
+            .map(|part| std::fs::read_to_string(CombinedWriter::part_path(dir.path(), part, "txt")).unwrap())
+            .collect();
+        assert!(!CombinedWriter::part_path(dir.path(), 5, "txt").exists());
+        assert_eq!(parts[0], format!("{}{}", chapters[0], chapters[1]));
+        assert_eq!(parts[1], chapters[2]);
+        assert_eq!(parts[2], chapters[3]);
+        assert_eq!(parts[3], chapters[4]);
+
+        // 每个章节都完整地出现在某一个分卷中
+        for chapter in &chapters {
+            assert_eq!(parts.iter().filter(|part| part.contains(chapter.as_str())).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_single_rerun_overwrites() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.txt");
+
+        let mut lengths = Vec::new();
+        for _ in 0..2 {
+            let mut writer = CombinedWriter::single(&path, false).unwrap().with_encoding(OutputEncoding::Utf16le);
+            writer.write_chunk("书名\n\n").unwrap();
+            writer.write_chunk("第一章\n\n正文\n\n").unwrap();
+            writer.finish().unwrap();
+            lengths.push(std::fs::metadata(&path).unwrap().len());
+        }
+        assert_eq!(lengths[0], lengths[1]);
+    }
+
+    #[test]
+    fn test_provenance_offsets_match_combined_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.txt");
+        let chapters = [("ch1.xhtml", "第一章\n\n开头"), ("ch2.xhtml", "Chapter 2\n\nThe end.")];
+
+        for (encoding, line_ending) in [
+            (OutputEncoding::Utf8, LineEnding::Lf),
+            (OutputEncoding::Utf16le, LineEnding::Lf),
+            (OutputEncoding::Utf8, LineEnding::Crlf),
+            (OutputEncoding::Utf16le, LineEnding::Crlf),
+        ] {
+            let mut writer = CombinedWriter::single(&path, false)
+                .unwrap()
+                .with_encoding(encoding)
+                .with_line_ending(line_ending);
+            writer.write_chunk("书名\n\n").unwrap();
+            let records: Vec<Provenance> = chapters
+                .iter()
+                .enumerate()
+                .map(|(index, (href, text))| {
+                    let range = writer.write_text(text, "\n\n---\n\n").unwrap();
+                    Provenance {
+                        index: index + 1,
+                        source_href: href.to_string(),
+                        title: text.lines().next().unwrap().to_string(),
+                        char_count: text.chars().count(),
+                        byte_start: range.start,
+                        byte_end: range.end,
+                    }
+                })
+                .collect();
+            writer.finish().unwrap();
+
+            let bytes = std::fs::read(&path).unwrap();
+            for (record, (_, text)) in records.iter().zip(chapters) {
+                let expected = encoding.encode(&line_ending.apply(text));
+                assert_eq!(&bytes[record.byte_start..record.byte_end], expected.as_slice());
+            }
+
+            let jsonl = provenance_jsonl(&records).unwrap();
+            let first: serde_json::Value = serde_json::from_str(jsonl.lines().next().unwrap()).unwrap();
+            assert_eq!(first["source_href"], "ch1.xhtml");
+            assert_eq!(first["char_count"], 7);
+        }
+    }
+
+    #[test]
+    fn test_sentinels_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.txt");
+        let chapters = [
+            Chapter {
+                title: "第一章".to_string(),
+                content: "第一段\n\n第二段\n".to_string(),
+                ..Chapter::default()
+            },
+            Chapter {
+                title: "<<<END CHAPTER 001>>>".to_string(),
+                content: "<<<END CHAPTER 002>>>\n<<<<已经有四个\n\n".to_string(),
+                ..Chapter::default()
+            },
+            Chapter {
+                title: String::new(),
+                content: "没有结尾换行".to_string(),
+                ..Chapter::default()
+            },
+        ];
+
+        for (encoding, line_ending) in [
+            (OutputEncoding::Utf8, LineEnding::Lf),
+            (OutputEncoding::Utf8, LineEnding::Crlf),
+            (OutputEncoding::Utf16le, LineEnding::Lf),
+        ] {
+            let mut writer = CombinedWriter::single(&path, false)
+                .unwrap()
+                .with_encoding(encoding)
+                .with_line_ending(line_ending);
+            writer.write_chunk("书名\n\n").unwrap();
+            for (index, chapter) in chapters.iter().enumerate() {
+                writer.write_chunk(&sentinel_chunk(index + 1, chapter)).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let split = split_combined(&path).unwrap();
+            assert_eq!(split.len(), chapters.len());
+            for (split, chapter) in split.iter().zip(&chapters) {
+                assert_eq!(split.title, chapter.title);
+                assert_eq!(split.content, chapter.content);
+            }
+        }
+    }
+
+    #[test]
+    fn test_one_source_boundary_per_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.txt");
+        let sources = ["OEBPS/Text/ch1.xhtml", "OEBPS/Text/ch2.xhtml", "OEBPS/Text/ch3.xhtml"];
+
+        let mut writer = CombinedWriter::single(&path, false).unwrap();
+        for source in sources {
+            writer.write_chunk(&source_boundary(source)).unwrap();
+            writer.write_chunk("标题\n\n# 不是边界的正文\n\n").unwrap();
+        }
+        writer.finish().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let boundaries: Vec<&str> = text.lines().filter(|line| line.starts_with("# --- ")).collect();
+        assert_eq!(
+            boundaries,
+            sources.map(|source| format!("# --- {} ---", source))
+        );
+    }
+
+    #[test]
+    fn test_metadata_trailer_after_last_chapter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.txt");
+        let chapters = ["第一章\n\n正文\n\n", "第二章\n\n正文\n\n"];
+        let trailer = metadata_trailer_chunk("title = \"书名\"\nauthor = \"作者\"\n");
+
+        let mut writer = CombinedWriter::single(&path, false).unwrap();
+        for chapter in chapters {
+            writer.write_chunk(chapter).unwrap();
+        }
+        writer.write_chunk(&trailer).unwrap();
+        writer.finish().unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.matches("===== METADATA =====").count(), 1);
+        assert_eq!(text.matches("===== END METADATA =====").count(), 1);
+        let start = text.find("===== METADATA =====").unwrap();
+        assert!(start > text.find(chapters[1]).unwrap());
+        assert!(text[start..].contains("title = \"书名\""));
+        assert!(text.ends_with("===== END METADATA =====\n"));
+    }
+}
@@ -0,0 +1,581 @@
+use super::chapter::Chapter;
+use super::media::MediaRef;
+use super::postprocess;
+use crate::config::{Config, RubyMode};
+use crate::utils::is_cjk;
+
+/// 由解析器驱动的文本提取器，严格和宽松两种解析器共用同一套提取规则
+pub struct Extractor<'a> {
+    config: &'a Config,
+    title: String,
+    content: String,
+    stack: Vec<Vec<u8>>,
+    emphasis: EmphasisStack,
+    // 每个打开的 <a> 对应一项，转换为 Markdown 链接时记录 (href, 打开 `[` 后正文的长度)
+    links: Vec<Option<(String, usize)>>,
+    // 每个打开的 <blockquote> 开始时正文的长度，结束时为其中的每一行加上 `> `
+    quotes: Vec<usize>,
+    media: Vec<MediaRef>,
+    // 已打开的 skip_tags 中的标签数，大于 0 时丢弃所有文本
+    skip_depth: usize,
+    // 启用 endnotes 时：按引用顺序排列的脚注 id，已收集的脚注 (id, 正文)，
+    // 正在收集的脚注 (id, 开始时正文的长度, 栈深度)，正在跳过文本的脚注引用的栈深度
+    note_refs: Vec<String>,
+    notes: Vec<(String, String)>,
+    note: Option<(String, usize, usize)>,
+    noteref_depth: Option<usize>,
+    // 每个打开的列表对应一项，有序列表记录下一项的序号，无序列表为 None
+    lists: Vec<Option<i64>>,
+    // 已打开的 <pre> 数，大于 0 时文本原样保留；刚打开 <pre> 时还没有收到文本
+    verbatim_depth: usize,
+    pre_opened: bool,
+    // 刚输出了独占一行的图片占位符，之后的文本另起一行
+    break_line: bool,
+    // 上一个文本节点以空白结尾（或遇到了只有空白的节点），写入下一段文本前补一个空格
+    space: bool,
+}
+
+impl<'a> Extractor<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            title: String::new(),
+            content: String::with_capacity(800),
+            stack: Vec::new(),
+            emphasis: EmphasisStack::default(),
+            links: Vec::new(),
+            quotes: Vec::new(),
+            media: Vec::new(),
+            skip_depth: 0,
+            note_refs: Vec::new(),
+            notes: Vec::new(),
+            note: None,
+            noteref_depth: None,
+            lists: Vec::new(),
+            verbatim_depth: 0,
+            pre_opened: false,
+            break_line: false,
+            space: false,
+        }
+    }
+
+    /// 元素开始，`attr` 按名称读取属性值
+    pub fn start(
+        &mut self,
+        name: &[u8],
+        attr: impl Fn(&str) -> anyhow::Result<Option<String>>,
+    ) -> anyhow::Result<()> {
+        let tags = &self.config.tags;
+        let options = &self.config.options;
+        let markdown = options.format.is_markdown() && !self.in_title();
+
+        if self.is_skip_tag(name) {
+            self.skip_depth += 1;
+        }
+        if name == b"rt" && options.ruby_mode == RubyMode::Parentheses {
+            self.push_text("(");
+        }
+        // 新的标题标签开始时清空旧标题，保证取最后一个标题标签的文本
+        if tags.title.contains(name) {
+            self.title.clear();
+        }
+        // 块级元素两侧的空白没有意义
+        if tags.block.contains(name) || tags.title.contains(name) || heading_level(name).is_some() {
+            self.space = false;
+        }
+        if options.endnotes {
+            self.start_note(name, &attr)?;
+        }
+        // 在带 id 的元素处插入锚点标记，便于后续解析 `#id` 形式的内部链接；
+        // 被跳过的元素、<head> 和标题元素的内容不在正文中，不插入标记
+        if options.anchor_markers
+            && self.skip_depth == 0
+            && !self.in_title()
+            && !tags.title.contains(name)
+            && name != b"head"
+            && !self.in_head()
+            && let Some(id) = attr("id")?
+        {
+            self.content.push_str(&format!("[[id:{}]]", id));
+        }
+        if (options.emphasis_markers || markdown)
+            && tags.inline.contains(name)
+            && !self.in_title()
+            && let Some(marker) = emphasis_marker(name)
+        {
+            self.flush_space("");
+            self.emphasis.open(name, marker, &mut self.content);
+        }
+        if markdown && !tags.title.contains(name) {
+            if let Some(level) = heading_level(name) {
+                self.start_line();
+                self.content.push_str(&"#".repeat(level));
+                self.content.push(' ');
+            } else if name == b"blockquote" {
+                self.start_line();
+                self.quotes.push(self.content.len());
+            } else if name == b"a" {
+                // 章节拆分后内部链接失效，只保留指向外部的链接
+                let href = attr("href")?.filter(|href| href.contains("://") || href.starts_with("mailto:"));
+                if href.is_some() {
+                    self.flush_space("");
+                }
+                self.links.push(href.map(|href| {
+                    self.content.push('[');
+                    (href, self.content.len())
+                }));
+            }
+        }
+        if options.image_placeholders && name == b"img" && self.skip_depth == 0 && !self.in_title() {
+            let placeholder = match attr("alt")?.filter(|alt| !alt.trim().is_empty()) {
+                Some(alt) => format!("[image: {}]", alt.trim()),
+                None => "[image]".to_string(),
+            };
+            self.start_line();
+            self.content.push_str(&placeholder);
+            self.space = false;
+            self.break_line = true;
+        }
+        if name == b"pre" {
+            // 代码块从新的一行开始
+            self.start_line();
+            self.verbatim_depth += 1;
+            self.pre_opened = true;
+            self.space = false;
+        }
+        if name == b"hr" && !options.scene_break.is_empty() && self.skip_depth == 0 && !self.in_title() {
+            self.start_line();
+            self.content.push('\n');
+            self.content.push_str(&options.scene_break);
+            self.content.push_str("\n\n");
+            self.space = false;
+        }
+        if name == b"ul" || name == b"ol" {
+            let start = if name == b"ol" {
+                Some(attr("start")?.and_then(|start| start.trim().parse().ok()).unwrap_or(1))
+            } else {
+                None
+            };
+            self.lists.push(start);
+        } else if name == b"li" && (markdown || options.list_markers) && self.skip_depth == 0 {
+            self.start_list_item();
+        }
+        if options.media_index
+            && matches!(name, b"audio" | b"video" | b"source")
+            && let Some(src) = attr("src")?
+        {
+            self.media.push(MediaRef {
+                element: String::from_utf8_lossy(name).into_owned(),
+                src,
+                media_type: attr("type")?,
+            });
+        }
+        self.stack.push(name.to_vec());
+        Ok(())
+    }
+
+    /// 脚注引用替换为重新编号的标记，其原有的文本被丢弃；脚注本身开始收集，结束时从正文中移出
+    fn start_note(
+        &mut self,
+        name: &[u8],
+        attr: &impl Fn(&str) -> anyhow::Result<Option<String>>,
+    ) -> anyhow::Result<()> {
+        let Some(epub_type) = attr("epub:type")? else {
+            return Ok(());
+        };
+        let has_type = |value: &str| epub_type.split_whitespace().any(|t| t == value);
+
+        if name == b"a"
+            && has_type("noteref")
+            && self.noteref_depth.is_none()
+            && let Some(id) = attr("href")?.and_then(|href| href.strip_prefix('#').map(String::from))
+        {
+            let number = match self.note_refs.iter().position(|note| *note == id) {
+                Some(index) => index + 1,
+                None => {
+                    self.note_refs.push(id);
+                    self.note_refs.len()
+                }
+            };
+            let marker = if self.config.options.format.is_markdown() {
+                format!("[^{}]", number)
+            } else {
+                format!("[{}]", number)
+            };
+            self.push_text(&marker);
+            self.skip_depth += 1;
+            self.noteref_depth = Some(self.stack.len());
+        } else if (has_type("footnote") || has_type("endnote"))
+            && self.note.is_none()
+            && let Some(id) = attr("id")?
+        {
+            self.note = Some((id, self.content.len(), self.stack.len()));
+        }
+        Ok(())
+    }
+
+    /// 已解码的文本节点
+    /// 两端的空白折叠为最多一个空格，使 `hello <a>world</a>` 这样跨越行内元素的单词保持分开；
+    /// `<pre>` 中的文本原样保留，不裁剪、不合并空白，也不合并折行
+    pub fn text(&mut self, text: &str) {
+        if self.verbatim_depth > 0 {
+            // 与 HTML 的规则一致，忽略紧跟在 <pre> 开始标签后的换行
+            let text = if std::mem::take(&mut self.pre_opened) {
+                text.strip_prefix("\r\n").or_else(|| text.strip_prefix('\n')).unwrap_or(text)
+            } else {
+                text
+            };
+            if !text.is_empty() {
+                self.push_text(text);
+            }
+            return;
+        }
+
+        let options = &self.config.options;
+        let raw = text;
+        let text = text.trim();
+        if raw.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+        if text.is_empty() {
+            return;
+        }
+
+        let text = if options.unwrap_lines {
+            postprocess::unwrap_lines(text)
+        } else {
+            text.to_string()
+        };
+        let text = if options.collapse_spaces {
+            postprocess::collapse_spaces(&text)
+        } else {
+            text
+        };
+        self.push_text(&text);
+        self.space = raw.ends_with(char::is_whitespace);
+    }
+
+    /// 原样加入文本，例如解析后的实体引用
+    pub fn push_text(&mut self, text: &str) {
+        let tags = &self.config.tags;
+        if self.skip_depth > 0 {
+            return;
+        }
+        // ruby 相关的标签不影响文本的归属，由其外层的标签决定
+        let Some(tag) = self.stack.iter().rev().find(|tag| !is_ruby_tag(tag)) else {
+            return;
+        };
+        if tags.title.contains::<[u8]>(tag) {
+            if std::mem::take(&mut self.space) {
+                push_space(&mut self.title, text);
+            }
+            self.title.push_str(text);
+        } else if self.verbatim_depth > 0
+            || tags.inline.contains::<[u8]>(tag)
+            || tags.block.contains::<[u8]>(tag)
+            || (self.config.options.format.is_markdown() && heading_level(tag).is_some())
+        {
+            self.end_placeholder_line();
+            // 空格出现在刚打开的强调标记之后时移到标记前，如 `c<em> d</em>` 输出 `c *d*`
+            if std::mem::take(&mut self.space) && !self.emphasis.space_before_open(&mut self.content) {
+                push_space(&mut self.content, text);
+            }
+            self.content.push_str(text);
+        }
+    }
+
+    /// 在插入强调、链接等标记前补上待写入的空格
+    fn flush_space(&mut self, next: &str) {
+        self.end_placeholder_line();
+        if std::mem::take(&mut self.space) {
+            push_space(&mut self.content, next);
+        }
+    }
+
+    pub fn end(&mut self, name: &[u8]) {
+        let tags = &self.config.tags;
+        let options = &self.config.options;
+        self.stack.pop();
+        if self.is_skip_tag(name) {
+            self.skip_depth = self.skip_depth.saturating_sub(1);
+        }
+        if name == b"rt" && options.ruby_mode == RubyMode::Parentheses {
+            self.push_text(")");
+        }
+        if name == b"ul" || name == b"ol" {
+            self.lists.pop();
+        }
+        if name == b"pre" {
+            self.verbatim_depth = self.verbatim_depth.saturating_sub(1);
+            self.pre_opened = false;
+            // 代码块之后的内容另起一行（pre 已配置为块级元素时由下面的块级处理补上换行）
+            if !tags.block.contains(name) {
+                self.start_line();
+            }
+        }
+        if self.noteref_depth == Some(self.stack.len()) {
+            self.noteref_depth = None;
+            self.skip_depth = self.skip_depth.saturating_sub(1);
+        }
+        let markdown = options.format.is_markdown() && !self.in_title() && !tags.title.contains(name);
+        if tags.block.contains(name) || tags.title.contains(name) || heading_level(name).is_some() {
+            self.space = false;
+        }
+
+        if markdown && name == b"a" {
+            if let Some(Some((href, position))) = self.links.pop() {
+                if self.content.len() == position {
+                    // 链接没有文本，移除打开的 `[`
+                    self.content.pop();
+                } else {
+                    self.content.push_str(&format!("]({})", href));
+                }
+            }
+        } else if markdown && heading_level(name).is_some() {
+            self.content.push('\n');
+        } else if markdown
+            && name == b"blockquote"
+            && let Some(position) = self.quotes.pop()
+        {
+            self.start_line();
+            let quoted: String = self.content[position..]
+                .split_inclusive('\n')
+                .map(|line| if line == "\n" { ">\n".to_string() } else { format!("> {}", line) })
+                .collect();
+            self.content.truncate(position);
+            self.content.push_str(&quoted);
+            // 空行结束引用，避免后面的段落被当作引用的延续
+            self.content.push('\n');
+        }
+
+        if options.emphasis_markers || options.format.is_markdown() {
+            if tags.block.contains(name) && name != b"br" {
+                // 强调标记不跨越块级元素，块结束时自动闭合
+                self.emphasis.close_all(&mut self.content);
+            } else {
+                self.emphasis.close(name, &mut self.content);
+            }
+        }
+
+        if let Some(marker) = &options.br_marker
+            && name == b"br"
+        {
+            // 例如 Markdown 的硬换行 "\\\n"
+            self.content.push_str(marker);
+        } else if options.unwrap_lines && name == b"br" {
+            // <br/> 视为段落内的软换行，以空格代替（中日文之间不加空格）
+            if self.content.chars().last().is_some_and(|c| !c.is_whitespace() && !is_cjk(c)) {
+                self.content.push(' ');
+            }
+        } else if (options.list_markers || options.format.is_markdown()) && matches!(name, b"li" | b"ul" | b"ol") {
+            // 列表项和列表的结束只换一次行，嵌套列表结束时不会留下多余的空行
+            self.start_line();
+            // Markdown 中最外层的列表以空行结束，后面的内容不会被当作列表的延续
+            if markdown && name != b"li" && self.lists.is_empty() {
+                self.content.push('\n');
+            }
+        } else if tags.block.contains(name) {
+            self.content.push('\n');
+        }
+
+        if self.note.as_ref().is_some_and(|(_, _, depth)| *depth == self.stack.len())
+            && let Some((id, position, _)) = self.note.take()
+        {
+            let body = self.content.split_off(position);
+            self.notes.push((id, body.trim().to_string()));
+            self.space = false;
+        }
+    }
+
+    /// 在正文末尾的 `---` 之后按编号输出脚注，没有被引用的脚注排在最后
+    fn append_notes(&mut self) {
+        if self.notes.is_empty() {
+            return;
+        }
+        let mut unreferenced = self.note_refs.len();
+        let mut notes: Vec<(usize, String)> = std::mem::take(&mut self.notes)
+            .into_iter()
+            .map(|(id, body)| match self.note_refs.iter().position(|note| *note == id) {
+                Some(index) => (index + 1, body),
+                None => {
+                    unreferenced += 1;
+                    (unreferenced, body)
+                }
+            })
+            .collect();
+        notes.sort_by_key(|(number, _)| *number);
+
+        let markdown = self.config.options.format.is_markdown();
+        let notes: Vec<String> = notes
+            .into_iter()
+            .map(|(number, body)| {
+                if markdown {
+                    format!("[^{}]: {}\n", number, body)
+                } else {
+                    format!("[{}] {}\n", number, body)
+                }
+            })
+            .collect();
+        self.start_line();
+        self.content.push_str("\n---\n\n");
+        self.content.push_str(&notes.join("\n"));
+    }
+
+    /// 列表项另起一行，有序列表以 `1. ` 开头，无序列表以 `- ` 开头，嵌套列表每层缩进两个空格
+    fn start_list_item(&mut self) {
+        let marker = match self.lists.last_mut() {
+            Some(Some(number)) => {
+                *number += 1;
+                format!("{}. ", *number - 1)
+            }
+            _ => "- ".to_string(),
+        };
+        self.start_line();
+        self.content.push_str(&"  ".repeat(self.lists.len().saturating_sub(1)));
+        self.content.push_str(&marker);
+        self.space = false;
+    }
+
+    /// 图片占位符之后的内容另起一行
+    fn end_placeholder_line(&mut self) {
+        if std::mem::take(&mut self.break_line) {
+            self.start_line();
+            self.space = false;
+        }
+    }
+
+    /// 保证接下来的内容从新的一行开始
+    fn start_line(&mut self) {
+        if !self.content.is_empty() && !self.content.ends_with('\n') {
+            self.content.push('\n');
+        }
+    }
+
+    /// skip_tags 中的标签，以及 ruby 注音中不输出的部分：`<rp>` 总是丢弃，`<rt>` 按 ruby_mode 决定
+    fn is_skip_tag(&self, name: &[u8]) -> bool {
+        let options = &self.config.options;
+        name == b"rp"
+            || (name == b"rt" && options.ruby_mode == RubyMode::Drop)
+            || options.skip_tags.iter().any(|tag| tag.as_bytes() == name)
+    }
+
+    /// 当前是否位于标题标签内
+    fn in_title(&self) -> bool {
+        self.stack.iter().any(|tag| self.config.tags.title.contains(tag.as_slice()))
+    }
+
+    fn in_head(&self) -> bool {
+        self.stack.iter().any(|tag| tag == b"head")
+    }
+
+    pub fn finish(mut self) -> Chapter {
+        self.append_notes();
+        Chapter {
+            title: self.title,
+            content: self.content,
+            media: self.media,
+        }
+    }
+}
+
+/// Markdown 风格的强调标记
+fn emphasis_marker(tag: &[u8]) -> Option<&'static str> {
+    match tag {
+        b"em" | b"i" => Some("*"),
+        b"strong" | b"b" => Some("**"),
+        _ => None,
+    }
+}
+
+/// 在 `target` 末尾补一个空格，避免行首和连续的空格；源文件中换行分隔的两个中日文字符之间不加空格
+fn push_space(target: &mut String, next: &str) {
+    let Some(last) = target.chars().last() else {
+        return;
+    };
+    let cjk_break = is_cjk(last) && next.chars().next().is_some_and(is_cjk);
+    if !last.is_whitespace() && !cjk_break {
+        target.push(' ');
+    }
+}
+
+fn is_ruby_tag(tag: &[u8]) -> bool {
+    matches!(tag, b"ruby" | b"rb" | b"rt" | b"rp")
+}
+
+/// `<h1>` 到 `<h6>` 的标题级别
+fn heading_level(tag: &[u8]) -> Option<usize> {
+    match tag {
+        [b'h', level @ b'1'..=b'6'] => Some((level - b'0') as usize),
+        _ => None,
+    }
+}
+
+/// 已打开的强调标记，保证标记正确嵌套
+#[derive(Default)]
+struct EmphasisStack {
+    // (标签, 标记, 打开标记后正文的长度)
+    open: Vec<(Vec<u8>, &'static str, usize)>,
+}
+
+impl EmphasisStack {
+    fn open(&mut self, tag: &[u8], marker: &'static str, content: &mut String) {
+        content.push_str(marker);
+        self.open.push((tag.to_vec(), marker, content.len()));
+    }
+
+    /// 闭合 `tag` 对应的标记；交叉嵌套时先闭合内层标记，闭合后再重新打开
+    fn close(&mut self, tag: &[u8], content: &mut String) {
+        let Some(index) = self.open.iter().rposition(|(open, ..)| open == tag) else {
+            return;
+        };
+        let inner = self.open.split_off(index + 1);
+        for (_, marker, position) in inner.iter().rev() {
+            Self::close_marker(marker, *position, content);
+        }
+        if let Some((_, marker, position)) = self.open.pop() {
+            Self::close_marker(marker, position, content);
+        }
+        for (tag, marker, _) in inner {
+            self.open(&tag, marker, content);
+        }
+    }
+
+    /// 末尾是刚打开、还没有文本的标记时，在这些标记前补空格（已有空白时不补），返回是否处理
+    fn space_before_open(&mut self, content: &mut String) -> bool {
+        let mut start = content.len();
+        let mut count = 0;
+        for (_, marker, position) in self.open.iter().rev() {
+            if *position != start {
+                break;
+            }
+            start -= marker.len();
+            count += 1;
+        }
+        if count == 0 {
+            return false;
+        }
+        if content[..start].chars().last().is_some_and(|c| !c.is_whitespace()) {
+            content.insert(start, ' ');
+            let len = self.open.len();
+            for (_, _, position) in &mut self.open[len - count..] {
+                *position += 1;
+            }
+        }
+        true
+    }
+
+    fn close_all(&mut self, content: &mut String) {
+        while let Some((_, marker, position)) = self.open.pop() {
+            Self::close_marker(marker, position, content);
+        }
+    }
+
+    fn close_marker(marker: &str, position: usize, content: &mut String) {
+        if content.len() == position {
+            // 标记之间没有文本，直接移除打开的标记
+            content.truncate(position - marker.len());
+        } else {
+            content.push_str(marker);
+        }
+    }
+}
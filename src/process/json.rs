@@ -0,0 +1,59 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use super::chapter::Chapter;
+use super::metadata::Metadata;
+
+/// JSON 输出的整本书：元数据字段与章节数组位于同一层
+#[derive(Serialize)]
+pub struct BookJson<'a> {
+    #[serde(flatten)]
+    pub metadata: &'a Metadata,
+    pub chapters: Vec<Chapter>,
+}
+
+impl BookJson<'_> {
+    /// serde_json 不转义非 ASCII 字符，中日韩文本原样输出
+    pub fn render(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)? + "\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::metadata::Package;
+
+    #[test]
+    fn test_book_json_keeps_unicode() {
+        let opf = r#"<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uid">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+<dc:title>채식주의자</dc:title>
+<dc:creator opf:role="aut">한강</dc:creator>
+<dc:identifier id="uid">urn:isbn:9788936433598</dc:identifier>
+</metadata>
+<manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let book = BookJson {
+            metadata: &package.metadata,
+            chapters: vec![Chapter {
+                title: "第一章".to_string(),
+                content: "他回来了。\n".to_string(),
+                ..Chapter::default()
+            }],
+        };
+        let json = book.render().unwrap();
+        assert!(json.contains("채식주의자"));
+        assert!(!json.contains("\\u"));
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["title"], "채식주의자");
+        assert_eq!(value["creators"][0]["name"], "한강");
+        assert_eq!(value["uid"], "urn:isbn:9788936433598");
+        assert_eq!(value["chapters"][0]["title"], "第一章");
+        assert_eq!(value["chapters"][0]["content"], "他回来了。\n");
+        assert!(value["chapters"][0].get("media").is_none());
+    }
+}
@@ -0,0 +1,72 @@
+use phf::{Map, phf_map};
+use whatlang::Lang;
+
+/// ISO 639-1 到 whatlang 使用的 ISO 639-3 代码
+static ISO_639_1: Map<&'static str, &'static str> = phf_map! {
+    "zh" => "cmn",
+    "en" => "eng",
+    "ja" => "jpn",
+    "ko" => "kor",
+    "fr" => "fra",
+    "de" => "deu",
+    "es" => "spa",
+    "pt" => "por",
+    "it" => "ita",
+    "ru" => "rus",
+    "uk" => "ukr",
+    "ar" => "ara",
+    "hi" => "hin",
+    "vi" => "vie",
+    "th" => "tha",
+    "nl" => "nld",
+    "pl" => "pol",
+    "tr" => "tur",
+};
+
+/// 将 `zh-CN`、`en`、`eng` 之类的语言标签转换为 whatlang 的语言
+pub fn parse_language(tag: &str) -> Option<Lang> {
+    let primary = tag
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let code = ISO_639_1.get(primary.as_str()).copied().unwrap_or(&primary);
+    Lang::from_code(code)
+}
+
+/// 检测出的语言与 `expected` 不同，且置信度不低于 `min_confidence` 时返回检测到的语言
+pub fn detect_foreign(text: &str, expected: Lang, min_confidence: f64) -> Option<Lang> {
+    let info = whatlang::detect(text)?;
+    (info.lang() != expected && info.confidence() >= min_confidence).then_some(info.lang())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_language() {
+        assert_eq!(parse_language("zh-CN"), Some(Lang::Cmn));
+        assert_eq!(parse_language("en"), Some(Lang::Eng));
+        assert_eq!(parse_language("jpn"), Some(Lang::Jpn));
+        assert_eq!(parse_language("xx"), None);
+    }
+
+    #[test]
+    fn test_detect_off_language_chapter() {
+        let chapters = [
+            "天色渐渐暗了下来，他沿着河岸慢慢地走回村子，心里一直想着白天发生的事情。",
+            "第二天早上，村里的人都聚集在广场上，等待着老村长宣布那个重要的消息。",
+            "Thank you for reading! Visit our website to discover more great books from the same author, and sign up for our newsletter.",
+            "老村长清了清嗓子，说道：从今天开始，我们每个人都要为这个村子出一份力。",
+        ];
+        let dropped: Vec<usize> = chapters
+            .iter()
+            .enumerate()
+            .filter(|(_, text)| detect_foreign(text, Lang::Cmn, 0.5).is_some())
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(dropped, [2]);
+    }
+}
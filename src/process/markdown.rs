@@ -0,0 +1,150 @@
+/// Obsidian 笔记的 YAML front matter
+pub struct FrontMatter<'a> {
+    pub title: &'a str,
+    pub index: usize,
+    pub book: &'a str,
+    pub authors: Vec<&'a str>,
+    /// 书籍的 subject，作为笔记的标签
+    pub tags: &'a [String],
+}
+
+impl FrontMatter<'_> {
+    pub fn render(&self) -> String {
+        let mut yaml = String::from("---\n");
+        yaml.push_str(&format!("title: {}\n", yaml_string(self.title)));
+        yaml.push_str(&format!("index: {}\n", self.index));
+        yaml.push_str(&format!("book: {}\n", yaml_string(self.book)));
+        match self.authors.as_slice() {
+            [] => {}
+            [author] => yaml.push_str(&format!("author: {}\n", yaml_string(author))),
+            authors => {
+                yaml.push_str("author:\n");
+                for author in authors {
+                    yaml.push_str(&format!("  - {}\n", yaml_string(author)));
+                }
+            }
+        }
+        if !self.tags.is_empty() {
+            yaml.push_str("tags:\n");
+            for tag in self.tags {
+                yaml.push_str(&format!("  - {}\n", yaml_string(&tag_name(tag))));
+            }
+        }
+        yaml.push_str("---\n");
+        yaml
+    }
+}
+
+/// 笔记的文件名，如 `001 - 标题.md`
+/// 去掉文件系统和 Obsidian 链接语法不允许的字符
+pub fn note_file_name(index: usize, title: &str) -> String {
+    let title: String = title
+        .chars()
+        .filter(|c| !c.is_control() && !r#"\/:*?"<>|#^[]"#.contains(*c))
+        .collect();
+    let title: String = title.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(100).collect();
+    if title.is_empty() {
+        format!("{:03}.md", index)
+    } else {
+        format!("{:03} - {}.md", index, title)
+    }
+}
+
+/// Obsidian 的标签不能包含空白，以 `-` 代替
+fn tag_name(subject: &str) -> String {
+    subject.trim().trim_start_matches('#').split_whitespace().collect::<Vec<_>>().join("-")
+}
+
+/// 双引号形式的 YAML 字符串
+fn yaml_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Options, OutputFormat};
+    use crate::process::Chapter;
+    use yaml_rust2::YamlLoader;
+
+    #[test]
+    fn test_obsidian_note() {
+        let config = Config {
+            options: Options {
+                format: OutputFormat::Obsidian,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let xhtml = r#"<html><body><h1>第一章 "重逢"</h1>
+<p>见 <a href="https://example.com/map">地图</a> 与 <a href="ch2.xhtml#n1">注释</a>。</p>
+<h2>小节</h2>
+<p>他<em>终于</em>回来了。</p>
+</body></html>"#;
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(chapter.title, r#"第一章 "重逢""#);
+        assert!(chapter.content.contains("[地图](https://example.com/map)"));
+        assert!(!chapter.content.contains("ch2.xhtml"));
+        assert!(chapter.content.contains("\n## 小节\n"));
+        assert!(chapter.content.contains("*终于*"));
+
+        let tags = ["Science Fiction".to_string(), "科幻".to_string()];
+        let front_matter = FrontMatter {
+            title: &chapter.title,
+            index: 1,
+            book: "书: 名",
+            authors: vec!["作者甲", "作者乙"],
+            tags: &tags,
+        }
+        .render();
+        let yaml = front_matter.strip_prefix("---\n").unwrap().strip_suffix("---\n").unwrap();
+        let doc = &YamlLoader::load_from_str(yaml).unwrap()[0];
+        assert_eq!(doc["title"].as_str(), Some(chapter.title.as_str()));
+        assert_eq!(doc["index"].as_i64(), Some(1));
+        assert_eq!(doc["book"].as_str(), Some("书: 名"));
+        assert_eq!(doc["author"][1].as_str(), Some("作者乙"));
+        assert_eq!(doc["tags"][0].as_str(), Some("Science-Fiction"));
+
+        assert_eq!(note_file_name(1, &chapter.title), "001 - 第一章 重逢.md");
+        assert_eq!(note_file_name(12, ""), "012.md");
+    }
+
+    #[test]
+    fn test_markdown_format() {
+        let config = Config {
+            options: Options {
+                format: OutputFormat::Markdown,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let xhtml = r#"<html><body><h1>第一章</h1>
+<h2>小节</h2>
+<p>他<strong>终于</strong>回来了，<em>悄悄地</em>。</p>
+<ul><li>苹果</li><li>梨<ul><li>雪梨</li></ul></li></ul>
+<blockquote><p>第一行</p><p>第二行</p></blockquote>
+<p>结尾</p>
+</body></html>"#;
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(chapter.title, "第一章");
+        assert_eq!(
+            chapter.content,
+            "## 小节\n他**终于**回来了，*悄悄地*。\n- 苹果\n- 梨\n  - 雪梨\n\n> 第一行\n> 第二行\n\n结尾\n"
+        );
+        assert_eq!(chapter.file_name(3, 2, OutputFormat::Markdown), "chapter_03.md");
+        assert_eq!(chapter.file_name(3, 2, OutputFormat::Text), "chapter_03.txt");
+    }
+}
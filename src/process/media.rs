@@ -0,0 +1,73 @@
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, value};
+
+/// 章节中 `<audio>`、`<video>`、`<source>` 引用的媒体
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaRef {
+    /// 元素名：audio、video 或 source
+    pub element: String,
+    /// `src` 属性；写入索引前会被解析为 zip 内的路径
+    pub src: String,
+    /// `type` 属性
+    pub media_type: Option<String>,
+}
+
+/// 按阅读顺序生成 media.toml，`media` 为 (章节路径, 媒体引用) 列表
+pub fn to_document(media: &[(String, MediaRef)]) -> DocumentMut {
+    let mut tables = ArrayOfTables::new();
+    for (chapter, media) in media {
+        let mut table = Table::new();
+        table["chapter"] = value(chapter.as_str());
+        table["element"] = value(media.element.as_str());
+        table["src"] = value(media.src.as_str());
+        if let Some(media_type) = &media.media_type {
+            table["type"] = value(media_type.as_str());
+        }
+        tables.push(table);
+    }
+
+    let mut doc = DocumentMut::new();
+    doc["media"] = Item::ArrayOfTables(tables);
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Options};
+    use crate::process::Chapter;
+
+    #[test]
+    fn test_media_index_from_audio_chapter() {
+        let config = Config {
+            options: Options {
+                media_index: true,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let xhtml = r#"<html><body>
+<p>听一听这段录音：</p>
+<audio src="../Audio/intro.mp3" controls="controls"/>
+<video controls="controls">
+<source src="../Video/clip.webm" type="video/webm"/>
+<source src="../Video/clip.mp4" type="video/mp4"/>
+</video>
+</body></html>"#;
+        let chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert_eq!(chapter.media.len(), 3);
+        assert_eq!(chapter.media[0].element, "audio");
+        assert_eq!(chapter.media[2].media_type.as_deref(), Some("video/mp4"));
+
+        let media: Vec<(String, MediaRef)> = chapter
+            .media
+            .into_iter()
+            .map(|media| ("OEBPS/Text/ch1.xhtml".to_string(), media))
+            .collect();
+        let doc = to_document(&media);
+        let tables = doc["media"].as_array_of_tables().unwrap();
+        assert_eq!(tables.len(), 3);
+        assert_eq!(tables.get(0).unwrap()["src"].as_str(), Some("../Audio/intro.mp3"));
+        assert!(tables.get(0).unwrap().get("type").is_none());
+        assert_eq!(tables.get(1).unwrap()["type"].as_str(), Some("video/webm"));
+    }
+}
@@ -1,51 +1,206 @@
-use std::fs::{self, File};
-use std::io::BufReader;
+use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
+use std::sync::LazyLock;
 
 use ahash::AHashMap;
 use anyhow::Result;
-use phf::{Map, phf_map};
 use quick_xml::de;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use toml_edit::{DocumentMut, Item, value};
 use zip::read::ZipFile;
 
-pub static ROLE_MAP: Map<&'static str, &'static str> = phf_map! {
-    "aut" => "author",
-    "edt" => "editor",
-    "trl" => "translator",
-    "ill" => "illustrator",
-};
+use super::output::write_text;
+use super::toc::TocFormat;
+use crate::config::{AuthorName, Config};
 
 #[derive(Debug, Deserialize)]
 pub struct Package {
+    /// 指向作为书籍唯一标识的 `<dc:identifier>` 的 id
+    #[serde(rename = "@unique-identifier")]
+    pub unique_identifier: Option<String>,
     pub metadata: Metadata,
     pub manifest: Manifest,
     pub spine: Spine,
 }
 
 impl Package {
-    pub fn from_opf(opf: &mut ZipFile<File>) -> Result<Self> {
-        let opf_reader = BufReader::new(opf);
-        let package: Package = de::from_reader(opf_reader)?;
+    pub fn from_opf<R: Read>(opf: &mut ZipFile<R>) -> Result<Self> {
+        Self::parse(BufReader::new(opf))
+    }
+
+    pub fn parse<R: BufRead>(reader: R) -> Result<Self> {
+        let mut package: Package = de::from_reader(reader)?;
+        package.metadata.resolve_metas();
+        package.metadata.resolve_uid(package.unique_identifier.as_deref());
         Ok(package)
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Metadata {
     pub title: Option<String>,
-    #[serde(rename = "creator", default)]
+    #[serde(rename(deserialize = "creator"), default)]
     pub creators: Vec<Creator>,
     pub language: Option<String>,
     pub description: Option<String>,
-    #[serde(rename = "subject", default)]
+    pub publisher: Option<String>,
+    pub rights: Option<String>,
+    /// EPUB2 可能有多个 `<dc:date opf:event="...">`，解析后取出版日期写入 `date`
+    #[serde(rename(deserialize = "date"), default, skip_serializing)]
+    pub dates: Vec<DcDate>,
+    /// 出版日期
+    #[serde(skip_deserializing)]
+    pub date: Option<String>,
+    #[serde(rename(deserialize = "subject"), default)]
     pub subjects: Vec<String>,
+    #[serde(rename(deserialize = "identifier"), default)]
+    pub identifiers: Vec<Identifier>,
+    // 已解析到下面的字段中，不再序列化
+    #[serde(rename(deserialize = "meta"), default, skip_serializing)]
+    pub metas: Vec<Meta>,
+    /// 由 package 的 unique-identifier 属性指向的标识符
+    #[serde(skip_deserializing)]
+    pub uid: Option<String>,
+    /// rendition:layout，`pre-paginated` 表示固定版式
+    #[serde(skip_deserializing)]
+    pub layout: Option<String>,
+    #[serde(skip_deserializing)]
+    pub orientation: Option<String>,
+    #[serde(skip_deserializing)]
+    pub spread: Option<String>,
+    /// dcterms:modified，EPUB3 记录的最后修改时间（不同于出版日期 dc:date）
+    #[serde(skip_deserializing)]
+    pub modified: Option<String>,
+    /// 源 EPUB 文件的 SHA-256
+    #[serde(skip_deserializing)]
+    pub source_sha256: Option<String>,
+    /// 估算的阅读时间
+    #[serde(skip_deserializing)]
+    pub reading_time: Option<String>,
 }
 
 impl Metadata {
-    pub fn write(&self, output_dir: &Path) -> Result<()> {
+    /// 从 `<meta property="...">` 中提取需要的属性
+    fn resolve_metas(&mut self) {
+        self.layout = self.meta_property("rendition:layout");
+        self.orientation = self.meta_property("rendition:orientation");
+        self.spread = self.meta_property("rendition:spread");
+        self.modified = self.meta_property("dcterms:modified");
+        // 优先使用标明为出版（publication）的日期，其次是没有标明事件的日期
+        self.date = self
+            .dates
+            .iter()
+            .find(|date| date.event.as_deref() == Some("publication"))
+            .or_else(|| self.dates.iter().find(|date| date.event.is_none()))
+            .or_else(|| self.dates.first())
+            .map(|date| date.value.trim().to_string());
+
+        // EPUB3 通过 <meta refines="#id" property="file-as"> 给出排序名
+        for creator in &mut self.creators {
+            let Some(id) = creator.id.as_deref().filter(|_| creator.file_as.is_none()) else {
+                continue;
+            };
+            let refines = format!("#{}", id);
+            creator.file_as = self
+                .metas
+                .iter()
+                .find(|meta| {
+                    meta.refines.as_deref() == Some(refines.as_str())
+                        && meta.property.as_deref() == Some("file-as")
+                })
+                .and_then(|meta| meta.value.as_ref())
+                .map(|value| value.trim().to_string());
+        }
+    }
+
+    /// 找不到 unique-identifier 指向的标识符时退回第一个标识符
+    fn resolve_uid(&mut self, unique_identifier: Option<&str>) {
+        let identifier = unique_identifier
+            .and_then(|uid| {
+                self.identifiers
+                    .iter()
+                    .find(|identifier| identifier.id.as_deref() == Some(uid))
+            })
+            .or_else(|| self.identifiers.first());
+        self.uid = identifier.map(|identifier| identifier.value.trim().to_string());
+    }
+
+    fn meta_property(&self, property: &str) -> Option<String> {
+        self.metas
+            .iter()
+            .find(|meta| meta.property.as_deref() == Some(property))
+            .and_then(|meta| meta.value.as_ref())
+            .map(|value| value.trim().to_string())
+    }
+
+    /// 从扉页式的第一章补全 OPF 中缺失的书名和作者，已有的字段不会被覆盖
+    /// `title` 为章节中的标题（通常是页面上最大的文字），没有时取第一行非作者行
+    /// 内容较长、不像扉页的章节直接忽略，宁可不填也不填错
+    pub fn fill_from_titlepage(&mut self, title: &str, content: &str) {
+        const MAX_LINES: usize = 8;
+        const MAX_LINE_CHARS: usize = 60;
+        static BYLINE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^(?:(?i:by)\s+(?<by>.+)|作者\s*[:：]\s*(?<label>.+)|(?<zhu>.+?)\s+著$)").unwrap()
+        });
+
+        let lines: Vec<&str> = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.len() > MAX_LINES || lines.iter().any(|line| line.chars().count() > MAX_LINE_CHARS) {
+            return;
+        }
+
+        let author = lines.iter().find_map(|line| {
+            let caps = BYLINE.captures(line)?;
+            let name = caps.name("by").or(caps.name("label")).or(caps.name("zhu"))?;
+            Some(name.as_str().trim().to_string())
+        });
+        let title = Some(title.trim())
+            .filter(|title| !title.is_empty())
+            .or_else(|| lines.iter().copied().find(|line| !BYLINE.is_match(line)));
+
+        if self.title.is_none() {
+            self.title = title.map(str::to_string);
+        }
+        if let (true, Some(name)) = (self.authors(AuthorName::Display).is_empty(), author) {
+            self.creators.push(Creator {
+                name,
+                role: Some("aut".to_string()),
+                id: None,
+                file_as: None,
+            });
+        }
+    }
+
+    /// 角色为作者（或未标明角色）的创作者姓名
+    pub fn authors(&self, form: AuthorName) -> Vec<&str> {
+        self.creators
+            .iter()
+            .filter(|creator| creator.role.as_deref().is_none_or(|role| role == "aut"))
+            .map(|creator| creator.name(form))
+            .collect()
+    }
+
+    /// 固定版式的书籍通常以图片或绝对定位排版，提取出的文本可能不可靠
+    pub fn is_fixed_layout(&self) -> bool {
+        self.layout.as_deref() == Some("pre-paginated")
+    }
+
+    pub fn write(&self, output_dir: &Path, config: &Config) -> Result<()> {
         let path = output_dir.join("metadata.toml");
+        let doc = self.to_document(config);
+        write_text(&path, &doc.to_string(), &config.options)?;
+        Ok(())
+    }
+
+    /// 配置中的 roles 为角色代码到名称的映射，已包含内置映射
+    /// author_name 决定写入显示名还是 file-as 排序名；设置了 creator_separator 时同一角色的多人连接为一个字符串
+    pub fn to_document(&self, config: &Config) -> DocumentMut {
+        let roles = &config.roles;
+        let author_name = config.options.author_name;
         // 创建 TOML 文档
         let mut doc = DocumentMut::new();
 
@@ -53,14 +208,31 @@ impl Metadata {
             doc["title"] = value(title.clone());
         }
 
+        // 按角色分组并保持出现顺序，同一角色有多人时写为数组
+        let mut grouped: Vec<(&str, Vec<&str>)> = Vec::new();
         for creator in &self.creators {
-            if let Some(role) = &creator.role {
-                let role_key = ROLE_MAP.get(role.as_str()).unwrap_or(&"unknown");
-                doc[role_key] = value(creator.name.clone());
-            } else {
-                doc["author"] = value(creator.name.clone());
+            let role_key = match &creator.role {
+                // 未知的角色代码直接作为键，避免不同角色互相覆盖
+                Some(role) => roles.get(role).map_or(role.as_str(), String::as_str),
+                None => "author",
+            };
+            let name = creator.name(author_name);
+            match grouped.iter_mut().find(|(key, _)| *key == role_key) {
+                Some((_, names)) => names.push(name),
+                None => grouped.push((role_key, vec![name])),
             }
         }
+        for (role_key, names) in grouped {
+            doc[role_key] = match (names.as_slice(), &config.options.creator_separator) {
+                ([name], _) => value(*name),
+                (names, Some(separator)) => value(names.join(separator)),
+                (names, None) => Item::Value(toml_edit::Value::Array(names.iter().copied().collect())),
+            };
+        }
+
+        if let Some(uid) = &self.uid {
+            doc["uid"] = value(uid.clone());
+        }
 
         if let Some(language) = &self.language {
             doc["language"] = value(language.clone());
@@ -70,6 +242,36 @@ impl Metadata {
             doc["description"] = value(description.clone());
         }
 
+        let publication = [("date", &self.date), ("publisher", &self.publisher), ("rights", &self.rights)];
+        for (key, field) in publication {
+            if let Some(field) = field {
+                doc[key] = value(field.trim());
+            }
+        }
+
+        if let Some(modified) = &self.modified {
+            doc["modified"] = value(modified.clone());
+        }
+
+        let renditions = [
+            ("layout", &self.layout),
+            ("orientation", &self.orientation),
+            ("spread", &self.spread),
+        ];
+        for (key, rendition) in renditions {
+            if let Some(rendition) = rendition {
+                doc[key] = value(rendition.clone());
+            }
+        }
+
+        if let Some(reading_time) = &self.reading_time {
+            doc["reading_time"] = value(reading_time.clone());
+        }
+
+        if let Some(source_sha256) = &self.source_sha256 {
+            doc["source_sha256"] = value(source_sha256.clone());
+        }
+
         // 处理数组字段 subject
         if !self.subjects.is_empty() {
             let mut array = toml_edit::Array::new();
@@ -79,9 +281,7 @@ impl Metadata {
             doc["subject"] = Item::Value(toml_edit::Value::Array(array));
         }
 
-        // 写入文件
-        fs::write(path, doc.to_string())?;
-        Ok(())
+        doc
     }
 }
 
@@ -92,30 +292,102 @@ pub struct Manifest {
 }
 
 impl Manifest {
+    /// 目录文件的 href 及其格式，优先使用 EPUB3 的 nav，其次是 spine 的 toc 属性或 NCX 类型的项
+    pub fn toc_href(&self, spine_toc: Option<&str>) -> Option<(&str, TocFormat)> {
+        let nav = self.items.iter().find(|item| {
+            item.properties
+                .as_deref()
+                .is_some_and(|properties| properties.split_whitespace().any(|p| p == "nav"))
+        });
+        if let Some(nav) = nav {
+            return Some((&nav.href, TocFormat::Nav));
+        }
+        self.items
+            .iter()
+            .find(|item| Some(item.id.as_str()) == spine_toc)
+            .or_else(|| {
+                self.items
+                    .iter()
+                    .find(|item| item.media_type == "application/x-dtbncx+xml")
+            })
+            .map(|ncx| (ncx.href.as_str(), TocFormat::Ncx))
+    }
+
     /// 转换为 HashMap<String, String> (id -> href)
     /// 过滤条件：
-    /// 1. 排除 id 包含 "cover" 的项
-    /// 2. 只保留 media_type 为 "application/xhtml+xml" 的项
-    pub fn into_map(self) -> AHashMap<String, String> {
+    /// 1. 排除 id 与 `exclude` 中任一模式完整匹配的项（默认为封面）
+    /// 2. 只保留可以提取文本的项（见 [`Manifest::resolve`]），href 为沿 fallback 链找到的项的 href
+    pub fn into_map(self, exclude: &[Regex]) -> AHashMap<String, String> {
+        let by_id = self.by_id();
         self.items
-            .into_iter()
-            .filter(|item| !item.id.contains("cover") && item.media_type == "application/xhtml+xml")
-            .map(|item| (item.id, item.href))
+            .iter()
+            .filter(|item| !is_excluded(&item.id, exclude))
+            .filter_map(|item| Some((item.id.clone(), Self::resolve(&by_id, item)?.href.clone())))
+            .collect()
+    }
+
+    /// spine 中引用了无法提取文本的项（如误放入 spine 的 CSS、NCX）时，返回这些项的 (href, media-type)
+    pub fn unsupported_in(&self, spine: &Spine, exclude: &[Regex]) -> Vec<(String, String)> {
+        let by_id = self.by_id();
+        spine
+            .itemrefs
+            .iter()
+            .filter_map(|itemref| by_id.get(itemref.idref.as_str()))
+            .filter(|item| !is_excluded(&item.id, exclude) && Self::resolve(&by_id, item).is_none())
+            .map(|item| (item.href.clone(), item.media_type.clone()))
             .collect()
     }
+
+    fn by_id(&self) -> AHashMap<&str, &ManifestItem> {
+        self.items.iter().map(|item| (item.id.as_str(), item)).collect()
+    }
+
+    /// 可提取文本的项：XHTML 和 HTML 直接使用，其他类型沿 fallback 链查找 XHTML 或 HTML 项；
+    /// 没有 fallback 的 SVG 也可作为内容文档
+    fn resolve<'a>(by_id: &AHashMap<&str, &'a ManifestItem>, item: &'a ManifestItem) -> Option<&'a ManifestItem> {
+        let mut current = item;
+        let mut visited = vec![item.id.as_str()];
+        while !matches!(current.media_type.as_str(), "application/xhtml+xml" | "text/html") {
+            let Some(fallback) = current.fallback.as_deref() else {
+                return (current.media_type == "image/svg+xml").then_some(current);
+            };
+            let fallback = by_id.get(fallback)?;
+            // 防止 fallback 链成环
+            if visited.contains(&fallback.id.as_str()) {
+                return None;
+            }
+            visited.push(&fallback.id);
+            current = fallback;
+        }
+        Some(current)
+    }
+}
+
+/// `exclude` 中的模式在配置加载时已锚定为完整匹配
+fn is_excluded(id: &str, exclude: &[Regex]) -> bool {
+    exclude.iter().any(|pattern| pattern.is_match(id))
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Spine {
+    /// EPUB2 中指向 NCX 的 manifest id
+    #[serde(rename = "@toc")]
+    pub toc: Option<String>,
     #[serde(rename = "itemref")]
     pub itemrefs: Vec<ItemRef>,
 }
 
 impl Spine {
-    pub fn into_hrefs(self, mut id_href_map: AHashMap<String, String>) -> Vec<String> {
-        self.itemrefs
+    /// 按阅读顺序返回章节的 href
+    /// `linear="no"` 的项（如注释、补充材料）不属于主阅读顺序，默认排除，`include_non_linear` 时排在最后
+    /// 同一个 idref 出现多次时每次都会返回，是否去重由调用方按解析后的 zip 路径决定
+    pub fn into_hrefs(self, id_href_map: AHashMap<String, String>, include_non_linear: bool) -> Vec<String> {
+        let (linear, non_linear): (Vec<_>, Vec<_>) = self.itemrefs.into_iter().partition(ItemRef::is_linear);
+        let non_linear = if include_non_linear { non_linear } else { Vec::new() };
+        linear
             .into_iter()
-            .filter_map(|itemref| id_href_map.remove(&itemref.idref))
+            .chain(non_linear)
+            .filter_map(|itemref| id_href_map.get(&itemref.idref).cloned())
             .collect()
     }
 }
@@ -128,28 +400,91 @@ pub struct ManifestItem {
     pub href: String,
     #[serde(rename = "@media-type")]
     pub media_type: String,
+    #[serde(rename = "@properties")]
+    pub properties: Option<String>,
+    /// 媒体类型无法直接处理时使用的替代项的 id
+    #[serde(rename = "@fallback")]
+    pub fallback: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ItemRef {
     #[serde(rename = "@idref")]
     pub idref: String,
+    #[serde(rename = "@linear")]
+    pub linear: Option<String>,
+}
+
+impl ItemRef {
+    /// 没有 linear 属性时默认为 `yes`
+    pub fn is_linear(&self) -> bool {
+        self.linear.as_deref().map(str::trim) != Some("no")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Identifier {
+    #[serde(rename(deserialize = "@id"))]
+    pub id: Option<String>,
+    #[serde(rename(deserialize = "$text"))]
+    pub value: String,
 }
 
 #[derive(Debug, Deserialize)]
+pub struct DcDate {
+    /// EPUB2 的 opf:event，如 publication、modification
+    #[serde(rename = "@event")]
+    pub event: Option<String>,
+    #[serde(rename = "$text")]
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Meta {
+    // EPUB3: <meta property="...">value</meta>
+    #[serde(rename = "@property")]
+    pub property: Option<String>,
+    #[serde(rename = "$text")]
+    pub value: Option<String>,
+    // EPUB2: <meta name="..." content="..."/>
+    #[serde(rename = "@name")]
+    pub name: Option<String>,
+    #[serde(rename = "@content")]
+    pub content: Option<String>,
+    // EPUB3: <meta refines="#id" property="...">，补充说明其他元素
+    #[serde(rename = "@refines")]
+    pub refines: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Creator {
     // $text获取元素和其子元素的文本内容
     // $value获取元素的文本内容
-    #[serde(rename = "$value")]
+    #[serde(rename(deserialize = "$value"))]
     pub name: String,
     // @表示属性
-    #[serde(rename = "@role")]
+    #[serde(rename(deserialize = "@role"))]
     pub role: Option<String>,
+    #[serde(rename(deserialize = "@id"))]
+    pub id: Option<String>,
+    /// 用于排序的姓名形式，如 "King, Stephen"
+    #[serde(rename(deserialize = "@file-as"))]
+    pub file_as: Option<String>,
+}
+
+impl Creator {
+    pub fn name(&self, form: AuthorName) -> &str {
+        match (form, &self.file_as) {
+            (AuthorName::FileAs, Some(file_as)) => file_as,
+            _ => &self.name,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Options;
     #[test]
     fn test_extract_opf_path() {
         let opf = r#"<?xml version="1.0" encoding="UTF-8"?>
@@ -189,4 +524,335 @@ mod tests {
         let package: Package = quick_xml::de::from_str(opf).unwrap();
         println!("{:#?}", package);
     }
+
+    #[test]
+    fn test_manifest_href_references_decoded() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>T</dc:title></metadata>
+<manifest>
+<item id="ch&#x31;" href="Text/ch&#x31;&#32;&amp;.xhtml" media-type="application/xhtml+xml"/>
+</manifest>
+<spine><itemref idref="ch&#49;"/></spine>
+</package>"#;
+        let package: Package = quick_xml::de::from_str(opf).unwrap();
+        let hrefs = package.spine.into_hrefs(package.manifest.into_map(&[]), false);
+        assert_eq!(hrefs, vec!["Text/ch1 &.xhtml".to_string()]);
+    }
+
+    #[test]
+    fn test_manifest_fallback_chain() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>回退</dc:title></metadata>
+<manifest>
+<item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/>
+<item id="svg" href="c2.svg" media-type="image/svg+xml" fallback="dtb"/>
+<item id="dtb" href="c2.dtb" media-type="application/x-dtbook+xml" fallback="c2"/>
+<item id="c2" href="c2.xhtml" media-type="application/xhtml+xml"/>
+<item id="loop1" href="c3.bin" media-type="application/octet-stream" fallback="loop2"/>
+<item id="loop2" href="c4.bin" media-type="application/octet-stream" fallback="loop1"/>
+</manifest>
+<spine><itemref idref="c1"/><itemref idref="svg"/><itemref idref="loop1"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let hrefs = package.spine.into_hrefs(package.manifest.into_map(&[]), false);
+        assert_eq!(hrefs, ["c1.xhtml", "c2.xhtml"]);
+    }
+
+    #[test]
+    fn test_spine_non_linear_items() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>非线性</dc:title></metadata>
+<manifest>
+<item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/>
+<item id="notes" href="notes.xhtml" media-type="application/xhtml+xml"/>
+<item id="c2" href="c2.xhtml" media-type="application/xhtml+xml"/>
+<item id="map" href="map.xhtml" media-type="application/xhtml+xml"/>
+<item id="c3" href="c3.xhtml" media-type="application/xhtml+xml"/>
+</manifest>
+<spine><itemref idref="c1"/><itemref idref="notes" linear="no"/><itemref idref="c2" linear="yes"/><itemref idref="map" linear="no"/><itemref idref="c3"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let hrefs = package.spine.into_hrefs(package.manifest.into_map(&[]), false);
+        assert_eq!(hrefs, ["c1.xhtml", "c2.xhtml", "c3.xhtml"]);
+
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let hrefs = package.spine.into_hrefs(package.manifest.into_map(&[]), true);
+        assert_eq!(hrefs, ["c1.xhtml", "c2.xhtml", "c3.xhtml", "notes.xhtml", "map.xhtml"]);
+    }
+
+    #[test]
+    fn test_spine_repeated_idrefs_kept() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>重复</dc:title></metadata>
+<manifest>
+<item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/>
+<item id="c2" href="c2.xhtml" media-type="application/xhtml+xml"/>
+</manifest>
+<spine><itemref idref="c1"/><itemref idref="c1"/><itemref idref="c2"/><itemref idref="c1"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let hrefs = package.spine.into_hrefs(package.manifest.into_map(&[]), false);
+        assert_eq!(hrefs, ["c1.xhtml", "c1.xhtml", "c2.xhtml", "c1.xhtml"]);
+    }
+
+    #[test]
+    fn test_spine_unsupported_media_types() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>误放</dc:title></metadata>
+<manifest>
+<item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+<item id="css" href="Styles/style.css" media-type="text/css"/>
+<item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/>
+<item id="c2" href="c2.html" media-type="text/html"/>
+<item id="map" href="map.svg" media-type="image/svg+xml"/>
+</manifest>
+<spine toc="ncx"><itemref idref="css"/><itemref idref="c1"/><itemref idref="ncx"/><itemref idref="c2"/><itemref idref="map"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let unsupported = package.manifest.unsupported_in(&package.spine, &[]);
+        assert_eq!(
+            unsupported,
+            [
+                ("Styles/style.css".to_string(), "text/css".to_string()),
+                ("toc.ncx".to_string(), "application/x-dtbncx+xml".to_string()),
+            ]
+        );
+
+        let hrefs = package.spine.into_hrefs(package.manifest.into_map(&[]), false);
+        assert_eq!(hrefs, ["c1.xhtml", "c2.html", "map.svg"]);
+    }
+
+    #[test]
+    fn test_exclude_ids_match_whole_ids() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>发现</dc:title></metadata>
+<manifest>
+<item id="cover" href="cover.xhtml" media-type="application/xhtml+xml"/>
+<item id="titlepage_cover" href="titlepage.xhtml" media-type="application/xhtml+xml"/>
+<item id="rediscovery" href="rediscovery.xhtml" media-type="application/xhtml+xml"/>
+<item id="discovery_01" href="discovery_01.xhtml" media-type="application/xhtml+xml"/>
+<item id="Cover.xhtml" href="Cover.xhtml" media-type="application/xhtml+xml"/>
+</manifest>
+<spine>
+<itemref idref="cover"/><itemref idref="titlepage_cover"/><itemref idref="rediscovery"/>
+<itemref idref="discovery_01"/><itemref idref="Cover.xhtml"/>
+</spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let exclude = crate::config::Options::default().exclude_ids;
+        let hrefs = package.spine.into_hrefs(package.manifest.into_map(&exclude), false);
+        assert_eq!(hrefs, ["rediscovery.xhtml", "discovery_01.xhtml"]);
+    }
+
+    #[test]
+    fn test_rendition_metadata() {
+        let opf = r#"<package version="3.0">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:title>漫画</dc:title>
+<meta property="rendition:layout">pre-paginated</meta>
+<dc:language>ja</dc:language>
+<meta property="rendition:orientation">portrait</meta>
+<meta name="cover" content="cover-image"/>
+<meta property="rendition:spread">landscape</meta>
+</metadata>
+<manifest><item id="p1" href="p1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="p1"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let metadata = package.metadata;
+        assert!(metadata.is_fixed_layout());
+        assert_eq!(metadata.layout.as_deref(), Some("pre-paginated"));
+        assert_eq!(metadata.orientation.as_deref(), Some("portrait"));
+        assert_eq!(metadata.spread.as_deref(), Some("landscape"));
+        assert_eq!(metadata.language.as_deref(), Some("ja"));
+    }
+
+    #[test]
+    fn test_dcterms_modified() {
+        let opf = r#"<package version="3.0">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:title>再版</dc:title>
+<dc:date>2001-05-01</dc:date>
+<meta property="dcterms:modified">2024-03-15T08:30:00Z</meta>
+</metadata>
+<manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"#;
+        let metadata = Package::parse(opf.as_bytes()).unwrap().metadata;
+        assert_eq!(metadata.modified.as_deref(), Some("2024-03-15T08:30:00Z"));
+
+        let doc = metadata.to_document(&Config::default());
+        assert_eq!(doc["modified"].as_str(), Some("2024-03-15T08:30:00Z"));
+    }
+
+    #[test]
+    fn test_unknown_roles_keep_raw_code() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+<dc:title>有声书</dc:title>
+<dc:creator opf:role="aut">作者</dc:creator>
+<dc:creator opf:role="nrt">朗读者</dc:creator>
+<dc:creator opf:role="pbl">出版者</dc:creator>
+</metadata>
+<manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+
+        let doc = package.metadata.to_document(&Config::default());
+        assert_eq!(doc["author"].as_str(), Some("作者"));
+        assert_eq!(doc["nrt"].as_str(), Some("朗读者"));
+        assert_eq!(doc["pbl"].as_str(), Some("出版者"));
+        assert!(doc.get("unknown").is_none());
+
+        // 配置文件中的映射在加载时合并到内置映射之上
+        let config: Config = config::Config::builder()
+            .add_source(config::File::from_str(
+                "[roles]\nnrt = \"narrator\"\ntrl = \"译者\"",
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert_eq!(config.roles["trl"], "译者");
+        let doc = package.metadata.to_document(&config);
+        assert_eq!(doc["author"].as_str(), Some("作者"));
+        assert_eq!(doc["narrator"].as_str(), Some("朗读者"));
+        assert_eq!(doc["pbl"].as_str(), Some("出版者"));
+    }
+
+    #[test]
+    fn test_multiple_creators_same_role() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+<dc:title>合著</dc:title>
+<dc:creator opf:role="aut">作者甲</dc:creator>
+<dc:creator opf:role="trl">译者</dc:creator>
+<dc:creator opf:role="aut">作者乙</dc:creator>
+</metadata>
+<manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        let doc = package.metadata.to_document(&Config::default());
+
+        let authors: Vec<&str> = doc["author"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|author| author.as_str())
+            .collect();
+        assert_eq!(authors, ["作者甲", "作者乙"]);
+        assert_eq!(doc["translator"].as_str(), Some("译者"));
+
+        let config = Config {
+            options: Options {
+                creator_separator: Some(", ".to_string()),
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let doc = package.metadata.to_document(&config);
+        assert_eq!(doc["author"].as_str(), Some("作者甲, 作者乙"));
+    }
+
+    #[test]
+    fn test_creator_file_as() {
+        let opf = r##"<package version="3.0">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+<dc:title>It</dc:title>
+<dc:creator opf:role="aut" opf:file-as="King, Stephen">Stephen King</dc:creator>
+<dc:creator id="trl" opf:role="trl">Jane Doe</dc:creator>
+<meta refines="#trl" property="file-as">Doe, Jane</meta>
+<dc:creator opf:role="ill">Anonymous</dc:creator>
+</metadata>
+<manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"##;
+        let metadata = Package::parse(opf.as_bytes()).unwrap().metadata;
+
+        let doc = metadata.to_document(&Config::default());
+        assert_eq!(doc["author"].as_str(), Some("Stephen King"));
+
+        let config = Config {
+            options: Options {
+                author_name: AuthorName::FileAs,
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let doc = metadata.to_document(&config);
+        assert_eq!(doc["author"].as_str(), Some("King, Stephen"));
+        assert_eq!(doc["translator"].as_str(), Some("Doe, Jane"));
+        assert_eq!(doc["illustrator"].as_str(), Some("Anonymous"));
+    }
+
+    #[test]
+    fn test_fill_from_titlepage() {
+        let opf = r#"<package>
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:language>en</dc:language></metadata>
+<manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"#;
+        let mut metadata = Package::parse(opf.as_bytes()).unwrap().metadata;
+        metadata.fill_from_titlepage("", "The Lost Garden\nA Novel\nby Jane Doe\nAcme Press");
+        assert_eq!(metadata.title.as_deref(), Some("The Lost Garden"));
+        let doc = metadata.to_document(&Config::default());
+        assert_eq!(doc["author"].as_str(), Some("Jane Doe"));
+
+        // 已有的字段不被覆盖
+        metadata.fill_from_titlepage("另一个书名", "作者：某人");
+        assert_eq!(metadata.title.as_deref(), Some("The Lost Garden"));
+        assert_eq!(metadata.creators.len(), 1);
+
+        // 正文式的长段落不视为扉页
+        let mut metadata = Package::parse(opf.as_bytes()).unwrap().metadata;
+        let prose = "It was a bright cold day in April, and the clocks were striking thirteen. by the way";
+        metadata.fill_from_titlepage("", prose);
+        assert!(metadata.title.is_none());
+        assert!(metadata.creators.is_empty());
+    }
+
+    #[test]
+    fn test_unique_identifier() {
+        let opf = r#"<package unique-identifier="BookId" version="2.0">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+<dc:identifier id="isbn" opf:scheme="ISBN">9787000000000</dc:identifier>
+<dc:title>标识符</dc:title>
+<dc:identifier id="BookId" opf:scheme="UUID">062b32e6-a657-42cf-95ba-5f9f6efd005a</dc:identifier>
+<dc:identifier opf:scheme="calibre">42</dc:identifier>
+</metadata>
+<manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"#;
+        let package = Package::parse(opf.as_bytes()).unwrap();
+        assert_eq!(package.metadata.identifiers.len(), 3);
+        assert_eq!(
+            package.metadata.uid.as_deref(),
+            Some("062b32e6-a657-42cf-95ba-5f9f6efd005a")
+        );
+    }
+
+    #[test]
+    fn test_publication_metadata() {
+        let opf = r#"<package version="2.0">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+<dc:title>出版信息</dc:title>
+<dc:date opf:event="modification">2020-01-01</dc:date>
+<dc:date opf:event="publication">1999-09-09</dc:date>
+<dc:publisher>某某出版社</dc:publisher>
+<dc:rights>All rights reserved</dc:rights>
+</metadata>
+<manifest><item id="c1" href="c1.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="c1"/></spine>
+</package>"#;
+        let metadata = Package::parse(opf.as_bytes()).unwrap().metadata;
+        assert_eq!(metadata.date.as_deref(), Some("1999-09-09"));
+
+        let doc = metadata.to_document(&Config::default());
+        assert_eq!(doc["date"].as_str(), Some("1999-09-09"));
+        assert_eq!(doc["publisher"].as_str(), Some("某某出版社"));
+        assert_eq!(doc["rights"].as_str(), Some("All rights reserved"));
+    }
 }
@@ -0,0 +1,227 @@
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use tempfile::NamedTempFile;
+
+use crate::config::{LineEnding, Options, OutputEncoding};
+
+impl OutputEncoding {
+    /// 将文本编码为输出字节
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            OutputEncoding::Utf8 => text.as_bytes().to_vec(),
+            OutputEncoding::Utf16le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        }
+    }
+
+    /// 编码后的字节数
+    pub fn encoded_len(self, text: &str) -> usize {
+        match self {
+            OutputEncoding::Utf8 => text.len(),
+            OutputEncoding::Utf16le => text.encode_utf16().count() * 2,
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            OutputEncoding::Utf8 => &[],
+            OutputEncoding::Utf16le => &[0xFF, 0xFE],
+        }
+    }
+}
+
+impl LineEnding {
+    /// 将文本中的换行符转换为配置的风格，原文中已有的 `\r\n` 不会变成 `\r\r\n`
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            LineEnding::Lf => Cow::Borrowed(text),
+            LineEnding::Crlf => Cow::Owned(text.replace("\r\n", "\n").replace('\n', "\r\n")),
+        }
+    }
+}
+
+/// 按配置的编码写入文本的文件，所有文本输出都应经过它，以保证编码一致
+/// 写入经过缓冲，写完后需调用 [`TextFile::finish`]，否则写入错误会在 drop 时被忽略
+pub struct TextFile {
+    file: BufWriter<File>,
+    encoding: OutputEncoding,
+    line_ending: LineEnding,
+    // 第一次写入时检查文件是否为空，为空则先写入 BOM（追加写入已有文件时不重复写入）
+    bom_checked: bool,
+    // 原子写入时的 (临时文件, 目标文件)，finish 时将临时文件重命名为目标文件
+    pending: Option<(PathBuf, PathBuf)>,
+}
+
+impl TextFile {
+    pub fn new(file: File, encoding: OutputEncoding) -> Self {
+        Self {
+            file: BufWriter::new(file),
+            encoding,
+            line_ending: LineEnding::default(),
+            bom_checked: false,
+            pending: None,
+        }
+    }
+
+    pub fn create(path: &Path, encoding: OutputEncoding) -> io::Result<Self> {
+        Ok(Self::new(File::create(path)?, encoding))
+    }
+
+    /// 先写入同目录下的 `<文件名>.tmp`，调用 [`TextFile::finish`] 时才重命名为 `path`，
+    /// 中断时不会留下写了一半的目标文件
+    pub fn create_atomic(path: &Path, encoding: OutputEncoding) -> io::Result<Self> {
+        let mut tmp = OsString::from(path.as_os_str());
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut file = Self::new(File::create(&tmp)?, encoding);
+        file.pending = Some((tmp, path.to_path_buf()));
+        Ok(file)
+    }
+
+    /// 按配置的编码、换行风格以及是否原子写入创建文件
+    pub fn create_for(path: &Path, options: &Options) -> io::Result<Self> {
+        let file = if options.atomic_write {
+            Self::create_atomic(path, options.output_encoding)?
+        } else {
+            Self::create(path, options.output_encoding)?
+        };
+        Ok(file.with_line_ending(options.line_ending))
+    }
+
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    pub fn set_encoding(&mut self, encoding: OutputEncoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    /// 写入文本，返回实际写入的字节数，包括在文件开头写入的 BOM
+    pub fn write_str(&mut self, text: &str) -> io::Result<usize> {
+        let mut written = 0;
+        if !self.bom_checked {
+            if self.file.get_ref().metadata()?.len() == 0 {
+                self.file.write_all(self.encoding.bom())?;
+                written += self.encoding.bom().len();
+            }
+            self.bom_checked = true;
+        }
+        let bytes = self.encoding.encode(&self.line_ending.apply(text));
+        self.file.write_all(&bytes)?;
+        Ok(written + bytes.len())
+    }
+
+    /// 写完后调用：刷新缓冲区，原子写入时将临时文件重命名为目标文件
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if let Some((tmp, path)) = self.pending.take() {
+            std::fs::rename(tmp, path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TextFile {
+    /// 没有调用 finish（如写入出错）时删除原子写入的临时文件
+    fn drop(&mut self) {
+        if let Some((tmp, _)) = self.pending.take() {
+            let _ = std::fs::remove_file(tmp);
+        }
+    }
+}
+
+/// 按配置的编码和换行风格一次写入整个文本文件，用于目录、索引等附属文件
+pub fn write_text(path: &Path, text: &str, options: &Options) -> io::Result<()> {
+    let mut file = TextFile::create_for(path, options)?;
+    file.write_str(text)?;
+    file.finish()
+}
+
+/// 先写入同目录下的临时文件再重命名，中断时不会留下写了一半的文件
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(bytes)?;
+    file.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16le_output_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.txt");
+        let text = "第一章 始まり\n\nHello, 世界 😀\n";
+
+        let mut file = TextFile::create(&path, OutputEncoding::Utf16le).unwrap();
+        file.write_str(&text[..10]).unwrap();
+        file.write_str(&text[10..]).unwrap();
+        drop(file);
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..2], [0xFF, 0xFE]);
+        let units: Vec<u16> = bytes[2..]
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(String::from_utf16(&units).unwrap(), text);
+
+        // 追加写入已有内容的文件时不再写入 BOM
+        let mut file = TextFile::new(File::options().append(true).open(&path).unwrap(), OutputEncoding::Utf16le);
+        file.write_str("!").unwrap();
+        file.finish().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), bytes.len() + 2);
+    }
+
+    #[test]
+    fn test_crlf_line_ending() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.txt");
+        let text = "第一章\n\n正文\r\n结尾\n";
+
+        let mut file = TextFile::create(&path, OutputEncoding::Utf8)
+            .unwrap()
+            .with_line_ending(LineEnding::Crlf);
+        let written = file.write_str(text).unwrap();
+        file.finish().unwrap();
+
+        let expected = "第一章\r\n\r\n正文\r\n结尾\r\n";
+        assert_eq!(written, expected.len());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_atomic_write_renames_on_finish() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.txt");
+        let tmp = dir.path().join("book.txt.tmp");
+        std::fs::write(&path, "旧内容").unwrap();
+
+        let mut file = TextFile::create_atomic(&path, OutputEncoding::Utf8).unwrap();
+        file.write_str("新内容").unwrap();
+        // 完成之前目标文件保持原样
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "旧内容");
+        assert!(tmp.exists());
+        file.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "新内容");
+        assert!(!tmp.exists());
+
+        // 没有完成就被丢弃时，删除临时文件，不影响目标文件
+        let mut file = TextFile::create_atomic(&path, OutputEncoding::Utf8).unwrap();
+        file.write_str("写了一半").unwrap();
+        drop(file);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "新内容");
+        assert!(!tmp.exists());
+    }
+}
@@ -0,0 +1,378 @@
+use ahash::AHashSet;
+use quick_xml::escape::{escape, partial_escape};
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::Options;
+use crate::utils::is_cjk;
+
+/// 删除单独成行的页码
+/// 上下相邻的非空行也匹配时，多半是表格或列表中的数字，予以保留
+pub fn strip_page_numbers(content: &str, pattern: &Regex) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let matches: Vec<bool> = lines
+        .iter()
+        .map(|line| !line.trim().is_empty() && pattern.is_match(line))
+        .collect();
+
+    let neighbour_matches = |index: usize| {
+        let prev = (0..index).rev().find(|&i| !lines[i].trim().is_empty());
+        let next = (index + 1..lines.len()).find(|&i| !lines[i].trim().is_empty());
+        prev.is_some_and(|i| matches[i]) || next.is_some_and(|i| matches[i])
+    };
+
+    let mut result = lines
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| !matches[index] || neighbour_matches(index))
+        .map(|(_, line)| *line)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+/// 将硬换行的文本合并为一行
+/// 换行两侧的空白会被去掉，中日文之间直接拼接，其他文字之间以空格连接
+pub fn unwrap_lines(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for (index, line) in text.split('\n').enumerate() {
+        let line = if index == 0 { line.trim_end() } else { line.trim() };
+        if line.is_empty() {
+            continue;
+        }
+        join_line(&mut result, line);
+    }
+    result
+}
+
+/// 把 `line` 接到 `result` 末尾，必要时插入一个空格
+fn join_line(result: &mut String, line: &str) {
+    if let (Some(prev), Some(next)) = (result.chars().last(), line.chars().next())
+        && !prev.is_whitespace()
+        && !(is_cjk(prev) || is_cjk(next))
+    {
+        result.push(' ');
+    }
+    result.push_str(line);
+}
+
+/// 将每个段落拆分为一行一个句子，已有的换行保持不变
+/// 中日文句号、问号、叹号后直接断句；西文的 `.`、`?`、`!` 后需有空白，且下一句不以小写字母开头
+/// 连续的结束符（如省略号）和其后的右引号、右括号归入前一句，中日文右引号后不断句
+pub fn split_sentences(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        result.push(c);
+        let cjk_end = matches!(c, '。' | '！' | '？');
+        if !(cjk_end || matches!(c, '.' | '!' | '?')) {
+            continue;
+        }
+        let mut quoted = false;
+        while let Some(&next) = chars.peek() {
+            if matches!(next, '.' | '!' | '?' | '。' | '！' | '？' | '…') {
+                result.push(next);
+                chars.next();
+            } else if matches!(next, '"' | '\'' | '”' | '’' | '」' | '』' | ')' | '）') {
+                quoted = true;
+                result.push(next);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        match chars.peek() {
+            None | Some('\n') => {}
+            // 中文引号后紧跟的多为“某某说”之类的说明，不断句
+            Some(&next) if cjk_end && !quoted && !next.is_whitespace() => result.push('\n'),
+            Some(&next) if next == ' ' || next == '\t' || next == '\u{3000}' => {
+                // 跳过句间空白，再看下一句的开头
+                let mut rest = chars.clone();
+                while rest.next_if(|c| matches!(c, ' ' | '\t' | '\u{3000}')).is_some() {}
+                if rest.peek().is_some_and(|c| !c.is_lowercase() && *c != '\n') {
+                    result.push('\n');
+                    chars = rest;
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// 将行内连续的空格和制表符合并为一个空格，换行保持不变
+pub fn collapse_spaces(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_space = false;
+    for c in text.chars() {
+        if c == ' ' || c == '\t' {
+            if !in_space {
+                result.push(' ');
+            }
+            in_space = true;
+        } else {
+            result.push(c);
+            in_space = false;
+        }
+    }
+    result
+}
+
+/// 按选项将不换行空格替换为普通空格、删除软连字符和零宽字符，最后进行 NFC 规范化
+pub fn normalize_chars(text: &str, options: &Options) -> String {
+    let text: String = text
+        .chars()
+        .filter_map(|c| match c {
+            '\u{00A0}' if options.replace_nbsp => Some(' '),
+            '\u{00AD}' if options.strip_soft_hyphens => None,
+            // 零宽空格、零宽非连接符、零宽连接符、词连接符和 BOM
+            '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{2060}' | '\u{FEFF}' if options.strip_zero_width => None,
+            c => Some(c),
+        })
+        .collect();
+    if options.normalize_nfc {
+        text.nfc().collect()
+    } else {
+        text
+    }
+}
+
+/// 连续的空行（包括只有空白的行）最多保留 `max` 行，嵌套的块级元素会产生多余的空行
+pub fn collapse_blank_lines(text: &str, max: usize) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut blanks = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            blanks += 1;
+            if blanks > max {
+                continue;
+            }
+        } else {
+            blanks = 0;
+        }
+        result.push_str(line);
+    }
+    result
+}
+
+/// 非空行中匹配任一推广模式的比例达到 `threshold` 时，视为推广、预告页面
+pub fn is_teaser(content: &str, patterns: &[Regex], threshold: f64) -> bool {
+    let lines: Vec<&str> = content.lines().filter(|line| !line.trim().is_empty()).collect();
+    if lines.is_empty() || patterns.is_empty() {
+        return false;
+    }
+    let matched = lines
+        .iter()
+        .filter(|line| patterns.iter().any(|pattern| pattern.is_match(line)))
+        .count();
+    matched as f64 / lines.len() as f64 >= threshold
+}
+
+/// 疑似乱码的字符比例：UTF-8 被误当作 Latin-1 / Windows-1252 解码后出现的
+/// `Ã©`、`Â `、`â€` 等组合，以及替换字符 U+FFFD，按每处一次计入
+pub fn mojibake_ratio(text: &str) -> f64 {
+    let mut chars = text.chars().filter(|c| !c.is_whitespace()).peekable();
+    let mut total = 0;
+    let mut suspicious = 0;
+    while let Some(c) = chars.next() {
+        total += 1;
+        let next = chars.peek().copied();
+        let double_encoded = match c {
+            '\u{FFFD}' => true,
+            // UTF-8 多字节序列的后续字节 0x80..=0xBF 在 Latin-1 中落在这个范围
+            'Ã' | 'Â' => next.is_some_and(|next| ('\u{80}'..='\u{BF}').contains(&next)),
+            // 引号、破折号等 U+20xx 标点的 UTF-8 以 E2 80 开头，在 Windows-1252 中为 "â€"
+            'â' => next == Some('€'),
+            _ => false,
+        };
+        if double_encoded {
+            suspicious += 1;
+        }
+    }
+    if total == 0 {
+        0.0
+    } else {
+        suspicious as f64 / total as f64
+    }
+}
+
+/// 将 `&`、`<`、`>` 重新转义为实体引用，`quotes` 为 true 时同时转义单双引号
+pub fn escape_xml(text: &str, quotes: bool) -> String {
+    if quotes {
+        escape(text).into_owned()
+    } else {
+        partial_escape(text).into_owned()
+    }
+}
+
+/// 跨章节收集去重后的段落，用于构建语料
+#[derive(Default)]
+pub struct UniqueParagraphs {
+    seen: AHashSet<String>,
+    paragraphs: Vec<String>,
+}
+
+impl UniqueParagraphs {
+    /// 加入一个章节的正文，每个非空行视为一个段落，保留第一次出现的顺序
+    pub fn add(&mut self, content: &str) {
+        for line in content.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if self.seen.insert(line.to_string()) {
+                self.paragraphs.push(line.to_string());
+            }
+        }
+    }
+
+    /// 每个段落之间以空行分隔，`sort` 为 true 时按字典序排序
+    pub fn into_text(mut self, sort: bool) -> String {
+        if sort {
+            self.paragraphs.sort_unstable();
+        }
+        let mut text = self.paragraphs.join("\n\n");
+        text.push('\n');
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::process::Chapter;
+
+    #[test]
+    fn test_collapse_blank_lines() {
+        let xhtml = "<html><body><div><div><section><p>第一段</p></section></div></div>\
+<div><div><p>第二段</p><div><p>第三段</p></div></div></div></body></html>";
+        let config = Config {
+            options: Options {
+                max_blank_lines: Some(1),
+                ..Options::default()
+            },
+            ..Config::default()
+        };
+        let mut chapter = Chapter::parse(xhtml.as_bytes(), &config).unwrap();
+        assert!(chapter.content.contains("第一段\n\n\n"));
+        chapter.post_process(&config);
+        assert_eq!(chapter.content, "第一段\n\n第二段\n第三段\n\n");
+
+        assert_eq!(collapse_blank_lines("a\n \n\t\n\nb\n", 0), "a\nb\n");
+        assert_eq!(collapse_blank_lines("a\n\n\n\nb", 2), "a\n\n\nb");
+    }
+
+    #[test]
+    fn test_unwrap_lines() {
+        assert_eq!(
+            unwrap_lines("This is a hard\n   wrapped\nparagraph."),
+            "This is a hard wrapped paragraph."
+        );
+        assert_eq!(unwrap_lines("第一行\n  第二行"), "第一行第二行");
+        assert_eq!(unwrap_lines("  leading stays\n\n"), "  leading stays");
+    }
+
+    #[test]
+    fn test_strip_page_numbers() {
+        let pattern = Regex::new(r"^\s*\d+\s*$").unwrap();
+        let content = "第一段\n 12 \n第二段有 3 个数字\n\n13\n第三段\n1\n2\n3\n";
+        assert_eq!(
+            strip_page_numbers(content, &pattern),
+            "第一段\n第二段有 3 个数字\n\n第三段\n1\n2\n3\n"
+        );
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        let text = r#"a < b && c > "d" 'e'"#;
+        assert_eq!(escape_xml(text, false), r#"a &lt; b &amp;&amp; c &gt; "d" 'e'"#);
+        assert_eq!(
+            escape_xml(text, true),
+            "a &lt; b &amp;&amp; c &gt; &quot;d&quot; &apos;e&apos;"
+        );
+    }
+
+    #[test]
+    fn test_teaser_among_real_chapters() {
+        let patterns = crate::config::Options::default().teaser_patterns;
+        let chapters = [
+            "他推开门，看见院子里站着一个陌生人。\n那人回过头来，冲他笑了笑。",
+            "Enjoyed it? Read the next chapter at www.example.com\nContinued on https://example.com/book/2\nThanks!",
+            "第二天一早，两人一起上了山。\n山路并不好走，但没有人抱怨。\n详见 https://example.com 的地图",
+        ];
+        let teasers: Vec<bool> = chapters
+            .iter()
+            .map(|content| is_teaser(content, &patterns, 0.5))
+            .collect();
+        assert_eq!(teasers, [false, true, false]);
+    }
+
+    #[test]
+    fn test_unique_paragraphs() {
+        let chapters = [
+            "本书由某某网站整理\n第一章的内容\n  \n版权所有\n",
+            "本书由某某网站整理\n第二章的内容\n版权所有",
+            "第一章的内容\n尾声",
+        ];
+        let mut unique = UniqueParagraphs::default();
+        for chapter in chapters {
+            unique.add(chapter);
+        }
+        assert_eq!(unique.paragraphs, ["本书由某某网站整理", "第一章的内容", "版权所有", "第二章的内容", "尾声"]);
+
+        let text = unique.into_text(true);
+        let paragraphs: Vec<&str> = text.trim_end().split("\n\n").collect();
+        assert_eq!(paragraphs.len(), 5);
+        assert!(paragraphs.is_sorted());
+    }
+
+    #[test]
+    fn test_mojibake_ratio() {
+        // "Café “quoted” — naïve" 的 UTF-8 字节被当作 Windows-1252 解码
+        let garbled = "CafÃ© â€œquotedâ€\u{9d} â€” naÃ¯ve";
+        assert!(mojibake_ratio(garbled) > 0.1);
+        assert_eq!(mojibake_ratio("Café “quoted” — naïve"), 0.0);
+        assert_eq!(mojibake_ratio("Ã la carte"), 0.0);
+        assert!(mojibake_ratio("乱码\u{FFFD}\u{FFFD}") > 0.1);
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        let text = "He left. Did she follow?  \"No!\" she said... e.g. this stays.\n他走了。她跟上了吗？「没有！」她说。\n";
+        assert_eq!(
+            split_sentences(text),
+            "He left.\nDid she follow?\n\"No!\" she said... e.g. this stays.\n他走了。\n她跟上了吗？\n「没有！」她说。\n"
+        );
+    }
+
+    #[test]
+    fn test_normalize_chars_independently() {
+        // 不换行空格、软连字符、零宽空格，以及分解形式的 é（e + U+0301）
+        let text = "a\u{00A0}b co\u{00AD}operate zero\u{200B}width cafe\u{0301}";
+        let normalize = |options: Options| normalize_chars(text, &options);
+
+        assert_eq!(normalize(Options::default()), text);
+        assert_eq!(
+            normalize(Options {
+                replace_nbsp: true,
+                ..Options::default()
+            }),
+            "a b co\u{00AD}operate zero\u{200B}width cafe\u{0301}"
+        );
+        assert_eq!(
+            normalize(Options {
+                strip_soft_hyphens: true,
+                strip_zero_width: true,
+                ..Options::default()
+            }),
+            "a\u{00A0}b cooperate zerowidth cafe\u{0301}"
+        );
+        assert_eq!(
+            normalize(Options {
+                normalize_nfc: true,
+                ..Options::default()
+            }),
+            "a\u{00A0}b co\u{00AD}operate zero\u{200B}width caf\u{00E9}"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use ahash::AHashSet;
+use toml_edit::{DocumentMut, value};
+
+use crate::config::ReadingSpeed;
+use crate::utils::is_cjk;
+
+/// 文本统计
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TextStats {
+    /// 非空白字符数
+    pub chars: usize,
+    /// 词数：中日文每个字计为一个词，其他文字按空白和标点分词
+    pub words: usize,
+    /// 中日文字符数
+    pub cjk_chars: usize,
+}
+
+impl TextStats {
+    pub fn add(&mut self, text: &str) {
+        let mut in_word = false;
+        for c in text.chars() {
+            if c.is_whitespace() {
+                in_word = false;
+                continue;
+            }
+            self.chars += 1;
+            if is_cjk(c) {
+                in_word = false;
+                if c.is_alphanumeric() {
+                    self.cjk_chars += 1;
+                    self.words += 1;
+                }
+            } else if c.is_alphanumeric() {
+                if !in_word {
+                    self.words += 1;
+                }
+                in_word = true;
+            } else {
+                in_word = false;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &TextStats) {
+        self.chars += other.chars;
+        self.words += other.words;
+        self.cjk_chars += other.cjk_chars;
+    }
+
+    /// stats.toml 的内容
+    pub fn to_document(&self, chapters: usize) -> DocumentMut {
+        let mut doc = DocumentMut::new();
+        doc["chapters"] = value(chapters as i64);
+        doc["characters"] = value(self.chars as i64);
+        doc["words"] = value(self.words as i64);
+        doc["cjk_characters"] = value(self.cjk_chars as i64);
+        doc
+    }
+}
+
+/// 根据书籍语言选择按字数还是按词数估算阅读时间（分钟，向上取整）
+pub fn reading_minutes(speed: &ReadingSpeed, stats: &TextStats, language: Option<&str>) -> usize {
+    let by_chars = language.is_some_and(|language| {
+        let language = language.to_ascii_lowercase();
+        language.starts_with("zh") || language.starts_with("ja")
+    });
+    let (amount, rate) = if by_chars {
+        (stats.cjk_chars, speed.chars_per_minute)
+    } else {
+        (stats.words, speed.words_per_minute)
+    };
+    amount.div_ceil(rate.max(1))
+}
+
+/// 格式化阅读时间，例如 `约 3 小时 20 分钟`
+pub fn format_reading_time(minutes: usize) -> String {
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("约 {} 分钟", minutes.max(1)),
+        (hours, 0) => format!("约 {} 小时", hours),
+        (hours, minutes) => format!("约 {} 小时 {} 分钟", hours, minutes),
+    }
+}
+
+/// 长度最大的 `n` 个章节的下标，长度相同时靠前的章节优先
+pub fn longest(lengths: &[usize], n: usize) -> AHashSet<usize> {
+    let mut indices: Vec<usize> = (0..lengths.len()).collect();
+    // sort_by_key 是稳定排序，长度相同的章节保持原有顺序
+    indices.sort_by_key(|&index| std::cmp::Reverse(lengths[index]));
+    indices.into_iter().take(n).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_stats() {
+        let mut stats = TextStats::default();
+        stats.add("Hello, world! 你好世界。\n안녕 하세요");
+        assert_eq!(stats.words, 2 + 4 + 2);
+        assert_eq!(stats.cjk_chars, 4);
+        assert_eq!(stats.chars, 12 + 5 + 5);
+    }
+
+    #[test]
+    fn test_reading_time_estimate() {
+        let speed = ReadingSpeed::default();
+        let stats = TextStats {
+            chars: 100_000,
+            words: 100_000,
+            cjk_chars: 100_000,
+        };
+        let minutes = reading_minutes(&speed, &stats, Some("zh-CN"));
+        assert_eq!(minutes, 200);
+        assert_eq!(format_reading_time(minutes), "约 3 小时 20 分钟");
+
+        let stats = TextStats {
+            chars: 60_000,
+            words: 15_000,
+            cjk_chars: 0,
+        };
+        assert_eq!(format_reading_time(reading_minutes(&speed, &stats, Some("en"))), "约 1 小时");
+        assert_eq!(format_reading_time(0), "约 1 分钟");
+    }
+
+    #[test]
+    fn test_longest_chapters() {
+        let lengths = [5, 50, 10, 40, 3, 40];
+        assert_eq!(longest(&lengths, 2), AHashSet::from([1, 3]));
+        assert_eq!(longest(&lengths, 10).len(), lengths.len());
+    }
+}
@@ -0,0 +1,385 @@
+use std::io::BufRead;
+
+use ahash::AHashMap;
+use anyhow::Result;
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+
+use crate::utils::{normalize_zip_path, resolve_reference};
+
+/// 目录文件的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TocFormat {
+    /// EPUB3 的 `<nav epub:type="toc">`
+    Nav,
+    /// EPUB2 的 NCX
+    Ncx,
+}
+
+/// 目录中的一项，保留原有的层级结构
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TocEntry {
+    pub title: String,
+    /// 规范化后的 zip 内路径，不含 `#` 后的片段
+    pub href: String,
+    pub children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    /// 自身及所有子项引用的路径
+    fn hrefs(&self) -> Vec<&str> {
+        let mut hrefs = vec![self.href.as_str()];
+        for child in &self.children {
+            hrefs.extend(child.hrefs());
+        }
+        hrefs
+    }
+}
+
+/// 不同目录格式中条目、标题和链接所在的元素
+struct Syntax {
+    entry: &'static [u8],
+    labels: &'static [&'static [u8]],
+    link: &'static [u8],
+    link_attr: &'static [u8],
+}
+
+impl TocFormat {
+    /// 目录中的标题是否优先于章节内的标题标签
+    /// EPUB3 nav 由作者维护，通常比章节内的标题更可靠；NCX 的标题只用于补全空标题
+    pub fn overrides_titles(self) -> bool {
+        self == TocFormat::Nav
+    }
+
+    fn syntax(self) -> Syntax {
+        match self {
+            // NCX 中的条目为 navPoint，标题在 navLabel/text 中，链接在 content 的 src 属性中
+            TocFormat::Ncx => Syntax {
+                entry: b"navPoint",
+                labels: &[b"text"],
+                link: b"content",
+                link_attr: b"src",
+            },
+            // nav 中的条目为 li，标题和链接都在 a（没有链接时为 span）中
+            TocFormat::Nav => Syntax {
+                entry: b"li",
+                labels: &[b"a", b"span"],
+                link: b"a",
+                link_attr: b"href",
+            },
+        }
+    }
+}
+
+/// 解析目录文件，`toc_path` 为目录文件在 zip 中的路径，用于解析其中的相对链接
+pub fn parse<R: BufRead>(reader: R, format: TocFormat, toc_path: &str) -> Result<Vec<TocEntry>> {
+    let mut reader = Reader::from_reader(reader);
+    // 不逐个裁剪文本节点，否则实体引用两侧的空格会丢失，标题在结束时统一规范空白
+    reader.config_mut().allow_dangling_amp = true;
+    reader.config_mut().check_end_names = false;
+    reader.config_mut().expand_empty_elements = true;
+    let syntax = format.syntax();
+
+    let mut roots = Vec::new();
+    let mut stack: Vec<TocEntry> = Vec::new();
+    // nav 文档中可能有多个 nav（如 landmarks），只解析目录
+    let mut in_toc = format == TocFormat::Ncx;
+    let mut in_label = false;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                let name = e.local_name();
+                let name = name.as_ref();
+                if format == TocFormat::Nav && name == b"nav" {
+                    in_toc = is_toc_nav(&e, &reader)?;
+                } else if in_toc && name == syntax.entry {
+                    stack.push(TocEntry::default());
+                } else if in_toc && !stack.is_empty() {
+                    if syntax.labels.contains(&name) {
+                        in_label = true;
+                    }
+                    if name == syntax.link
+                        && let Some(href) = e.try_get_attribute(syntax.link_attr)?
+                        && let Some(entry) = stack.last_mut()
+                    {
+                        let href = href.decode_and_unescape_value(reader.decoder())?;
+                        entry.href = normalize_zip_path(toc_path, href.into_owned());
+                    }
+                }
+            }
+            Event::Text(text) if in_label => {
+                if let Some(entry) = stack.last_mut() {
+                    entry.title.push_str(&text.html_content()?);
+                }
+            }
+            Event::GeneralRef(reference) if in_label => {
+                if let Some(entry) = stack.last_mut()
+                    && let Some(decoded) = resolve_reference(&reference)?
+                {
+                    entry.title.push_str(&decoded);
+                }
+            }
+            Event::End(e) => {
+                let name = e.local_name();
+                let name = name.as_ref();
+                if syntax.labels.contains(&name) {
+                    in_label = false;
+                } else if in_toc && name == syntax.entry {
+                    if let Some(mut entry) = stack.pop() {
+                        entry.title = entry.title.split_whitespace().collect::<Vec<_>>().join(" ");
+                        match stack.last_mut() {
+                            Some(parent) => parent.children.push(entry),
+                            None => roots.push(entry),
+                        }
+                    }
+                } else if format == TocFormat::Nav && name == b"nav" {
+                    in_toc = false;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(roots)
+}
+
+fn is_toc_nav<R>(nav: &BytesStart, reader: &Reader<R>) -> Result<bool> {
+    for attr in nav.attributes() {
+        let attr = attr?;
+        if attr.key.local_name().as_ref() == b"type" {
+            let value = attr.decode_and_unescape_value(reader.decoder())?;
+            return Ok(value.split_whitespace().any(|kind| kind == "toc"));
+        }
+    }
+    Ok(false)
+}
+
+/// 将目录写为纯文本大纲，每深一级缩进两个空格
+pub fn outline(toc: &[TocEntry]) -> String {
+    fn push_entries(entries: &[TocEntry], depth: usize, outline: &mut String) {
+        for entry in entries {
+            outline.push_str(&"  ".repeat(depth));
+            outline.push_str(&entry.title);
+            outline.push('\n');
+            push_entries(&entry.children, depth + 1, outline);
+        }
+    }
+
+    let mut outline = String::new();
+    push_entries(toc, 0, &mut outline);
+    outline
+}
+
+/// 路径到目录标题的映射，同一文件出现多次时（如指向文件内的不同片段）取第一个条目的标题
+pub fn titles(toc: &[TocEntry]) -> AHashMap<&str, &str> {
+    fn collect<'a>(entries: &'a [TocEntry], titles: &mut AHashMap<&'a str, &'a str>) {
+        for entry in entries {
+            if !entry.href.is_empty() && !entry.title.is_empty() {
+                titles.entry(entry.href.as_str()).or_insert(entry.title.as_str());
+            }
+            collect(&entry.children, titles);
+        }
+    }
+
+    let mut titles = AHashMap::new();
+    collect(toc, &mut titles);
+    titles
+}
+
+/// 每个章节在目录中的层级（从 1 开始），同一文件出现多次时取最浅的层级
+/// 不在目录中的章节视为前一章节的延续，沿用其层级；开头的章节默认为 1
+pub fn depths(toc: &[TocEntry], chapters: &[String]) -> Vec<usize> {
+    fn collect<'a>(entries: &'a [TocEntry], depth: usize, depths: &mut AHashMap<&'a str, usize>) {
+        for entry in entries {
+            let current = depths.entry(entry.href.as_str()).or_insert(depth);
+            *current = (*current).min(depth);
+            collect(&entry.children, depth + 1, depths);
+        }
+    }
+
+    let mut by_href = AHashMap::new();
+    collect(toc, 1, &mut by_href);
+
+    let mut previous = 1;
+    chapters
+        .iter()
+        .map(|chapter| {
+            previous = by_href.get(chapter.as_str()).copied().unwrap_or(previous);
+            previous
+        })
+        .collect()
+}
+
+/// 按两级目录（部分 → 章节）计算每个章节前需要插入的部分标题
+/// 只有带子项的顶级条目视为部分，仅在进入新的部分时插入一次
+pub fn part_headings(toc: &[TocEntry], chapters: &[String]) -> Vec<Option<String>> {
+    let mut parts: AHashMap<&str, &str> = AHashMap::new();
+    for part in toc.iter().filter(|entry| !entry.children.is_empty()) {
+        for href in part.hrefs() {
+            parts.entry(href).or_insert(&part.title);
+        }
+    }
+
+    let mut current = None;
+    chapters
+        .iter()
+        .map(|chapter| {
+            let part = parts.get(chapter.as_str()).copied();
+            if part.is_some() && part != current {
+                current = part;
+                part.map(|title| format!("== {} ==", title))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nested_ncx() {
+        let ncx = r#"<?xml version="1.0"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/"><navMap>
+<navPoint id="p1"><navLabel><text>Part I: Beginnings</text></navLabel><content src="Text/part1.xhtml"/>
+  <navPoint id="c1"><navLabel><text>Chapter 1</text></navLabel><content src="Text/ch1.xhtml#start"/></navPoint>
+  <navPoint id="c2"><navLabel><text>Chapter 2 &amp; more</text></navLabel><content src="Text/ch2.xhtml"/></navPoint>
+</navPoint>
+<navPoint id="a"><navLabel><text>Afterword</text></navLabel><content src="Text/after.xhtml"/></navPoint>
+</navMap></ncx>"#;
+        let toc = parse(ncx.as_bytes(), TocFormat::Ncx, "OEBPS/toc.ncx").unwrap();
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].title, "Part I: Beginnings");
+        assert_eq!(toc[0].href, "OEBPS/Text/part1.xhtml");
+        assert_eq!(toc[0].children[0].href, "OEBPS/Text/ch1.xhtml");
+        assert_eq!(toc[0].children[1].title, "Chapter 2 & more");
+        assert!(toc[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_outline_indents_nested_ncx() {
+        let ncx = r#"<ncx><navMap>
+<navPoint><navLabel><text>第一部</text></navLabel><content src="p1.xhtml"/>
+  <navPoint><navLabel><text>第一章</text></navLabel><content src="c1.xhtml"/>
+    <navPoint><navLabel><text>第一节</text></navLabel><content src="c1.xhtml#s1"/></navPoint>
+  </navPoint>
+  <navPoint><navLabel><text>第二章</text></navLabel><content src="c2.xhtml"/></navPoint>
+</navPoint>
+<navPoint><navLabel><text>后记</text></navLabel><content src="after.xhtml"/></navPoint>
+</navMap></ncx>"#;
+        let toc = parse(ncx.as_bytes(), TocFormat::Ncx, "toc.ncx").unwrap();
+        assert_eq!(outline(&toc), "第一部\n  第一章\n    第一节\n  第二章\n后记\n");
+    }
+
+    #[test]
+    fn test_parse_nested_nav_skips_landmarks() {
+        let nav = r#"<html xmlns:epub="http://www.idpf.org/2007/ops"><body>
+<nav epub:type="toc"><ol>
+<li><span>Part I</span><ol>
+  <li><a href="../Text/ch1.xhtml">Chapter 1</a></li>
+</ol></li>
+<li><a href="../Text/ch2.xhtml">Chapter 2</a></li>
+</ol></nav>
+<nav epub:type="landmarks"><ol><li><a href="../Text/cover.xhtml">Cover</a></li></ol></nav>
+</body></html>"#;
+        let toc = parse(nav.as_bytes(), TocFormat::Nav, "OEBPS/Nav/nav.xhtml").unwrap();
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].title, "Part I");
+        assert_eq!(toc[0].children[0].title, "Chapter 1");
+        assert_eq!(toc[0].children[0].href, "OEBPS/Text/ch1.xhtml");
+        assert_eq!(toc[1].title, "Chapter 2");
+    }
+
+    #[test]
+    fn test_nav_titles_by_href() {
+        let nav = r#"<html xmlns:epub="http://www.idpf.org/2007/ops"><body>
+<nav epub:type="toc"><ol>
+<li><a href="ch1.xhtml">第一章 出发</a><ol>
+  <li><a href="ch1.xhtml#s2">第二节</a></li>
+</ol></li>
+<li><span>无链接</span></li>
+<li><a href="ch2.xhtml#top">第二章 归来</a></li>
+</ol></nav>
+</body></html>"#;
+        let toc = parse(nav.as_bytes(), TocFormat::Nav, "OEBPS/nav.xhtml").unwrap();
+        let titles = titles(&toc);
+        assert_eq!(titles.len(), 2);
+        assert_eq!(titles["OEBPS/ch1.xhtml"], "第一章 出发");
+        assert_eq!(titles["OEBPS/ch2.xhtml"], "第二章 归来");
+    }
+
+    #[test]
+    fn test_ncx_titles_nested_with_fragments() {
+        let ncx = r#"<ncx><navMap>
+<navPoint><navLabel><text>Part One</text></navLabel><content src="Text/part1.xhtml"/>
+  <navPoint><navLabel><text>The Arrival</text></navLabel><content src="Text/ch1.xhtml#start"/>
+    <navPoint><navLabel><text>Scene 2</text></navLabel><content src="Text/ch1.xhtml#scene2"/></navPoint>
+  </navPoint>
+</navPoint>
+<navPoint><navLabel><text>Epilogue</text></navLabel><content src="Text/ch2.xhtml"/></navPoint>
+</navMap></ncx>"#;
+        let toc = parse(ncx.as_bytes(), TocFormat::Ncx, "OEBPS/toc.ncx").unwrap();
+        let titles = titles(&toc);
+        assert_eq!(titles["OEBPS/Text/part1.xhtml"], "Part One");
+        assert_eq!(titles["OEBPS/Text/ch1.xhtml"], "The Arrival");
+        assert_eq!(titles["OEBPS/Text/ch2.xhtml"], "Epilogue");
+        assert!(!TocFormat::Ncx.overrides_titles());
+    }
+
+    #[test]
+    fn test_part_headings_for_two_level_toc() {
+        let chapter = |title: &str, href: &str| TocEntry {
+            title: title.to_string(),
+            href: href.to_string(),
+            children: Vec::new(),
+        };
+        let toc = vec![
+            chapter("Preface", "pre.xhtml"),
+            TocEntry {
+                children: vec![chapter("Chapter 1", "ch1.xhtml"), chapter("Chapter 2", "ch2.xhtml")],
+                ..chapter("Part I: Beginnings", "part1.xhtml")
+            },
+            TocEntry {
+                children: vec![chapter("Chapter 3", "ch3.xhtml")],
+                ..chapter("Part II: Endings", "ch3.xhtml")
+            },
+        ];
+        let chapters = ["pre.xhtml", "part1.xhtml", "ch1.xhtml", "ch2.xhtml", "ch3.xhtml", "notes.xhtml"]
+            .map(String::from);
+        assert_eq!(
+            part_headings(&toc, &chapters),
+            [
+                None,
+                Some("== Part I: Beginnings ==".to_string()),
+                None,
+                None,
+                Some("== Part II: Endings ==".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_depths_as_markdown_headings() {
+        let ncx = r#"<ncx><navMap>
+<navPoint><navLabel><text>Part I</text></navLabel><content src="part1.xhtml"/>
+  <navPoint><navLabel><text>Chapter 1</text></navLabel><content src="ch1.xhtml"/></navPoint>
+  <navPoint><navLabel><text>Chapter 2</text></navLabel><content src="ch2.xhtml"/></navPoint>
+</navPoint>
+<navPoint><navLabel><text>Part II</text></navLabel><content src="part2.xhtml"/></navPoint>
+</navMap></ncx>"#;
+        let toc = parse(ncx.as_bytes(), TocFormat::Ncx, "toc.ncx").unwrap();
+        let chapters = ["part1.xhtml", "ch1.xhtml", "ch1_split.xhtml", "ch2.xhtml", "part2.xhtml"].map(String::from);
+        let headings: Vec<String> = depths(&toc, &chapters)
+            .into_iter()
+            .map(|depth| "#".repeat(depth))
+            .collect();
+        assert_eq!(headings, ["#", "##", "##", "##", "#"]);
+    }
+}
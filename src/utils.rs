@@ -1,4 +1,16 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
+use filetime::FileTime;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::BytesRef;
+use sha2::{Digest, Sha256};
+
+/// 将相对于 `opf_path` 的 href 解析为 zip 内的路径，href 会先经过 [`decode_href`]
 pub fn normalize_zip_path(opf_path: &str, rel: String) -> String {
+    let rel = decode_href(&rel);
     let mut result = String::with_capacity(opf_path.len() + rel.len());
 
     if let Some(pos) = opf_path.rfind('/') {
@@ -9,9 +21,8 @@ pub fn normalize_zip_path(opf_path: &str, rel: String) -> String {
     for comp in rel.split('/') {
         match comp {
             ".." => {
-                if let Some(pos) = result.rfind('/') {
-                    result.truncate(pos);
-                }
+                // 没有上级目录时回到 zip 根目录
+                result.truncate(result.rfind('/').unwrap_or(0));
             }
             "." | "" => {} // 忽略这两种情况
             _ => {
@@ -23,5 +34,128 @@ pub fn normalize_zip_path(opf_path: &str, rel: String) -> String {
         }
     }
 
+    log::debug!("Normalized path: {}", result);
     result
 }
+
+/// 去掉 href 中 `#` 之后的片段并解码百分号编码（如 `%20`、`%E4%B8%AD`）
+/// 不构成有效编码的 `%` 原样保留
+pub fn decode_href(href: &str) -> String {
+    let path = href.split('#').next().unwrap_or_default().as_bytes();
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let hex = path.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if path[i] == b'%' => {
+                bytes.push(byte);
+                i += 3;
+            }
+            _ => {
+                bytes.push(path[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// 解析实体引用（`&#x41;`、`&amp;`、`&nbsp;` 等）
+/// 无法识别的命名实体返回 None
+pub fn resolve_reference(reference: &BytesRef) -> Result<Option<String>> {
+    if let Some(ch) = reference.resolve_char_ref()? {
+        return Ok(Some(ch.to_string()));
+    }
+    let name = reference.decode()?;
+    Ok(resolve_predefined_entity(&name).map(str::to_string))
+}
+
+/// 是否为中日文字符（包括全角标点），这类文字之间不使用空格分词
+/// 韩文使用空格分词，因此不包含在内
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{2E80}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF00}'..='\u{FFEF}'
+        | '\u{20000}'..='\u{2FA1F}'
+    )
+}
+
+/// 流式计算文件的 SHA-256，返回十六进制字符串
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// 内存中数据的 SHA-256，返回十六进制字符串
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 将 `dir` 下所有文件的修改时间设为 `source` 的修改时间
+pub fn copy_mtime(source: &Path, dir: &Path) -> Result<()> {
+    let mtime = FileTime::from_last_modification_time(&source.metadata()?);
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() {
+            copy_mtime(source, &path)?;
+        } else {
+            filetime::set_file_mtime(&path, mtime)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_decodes_hrefs() {
+        let opf = "OEBPS/content.opf";
+        assert_eq!(normalize_zip_path(opf, "Text/chapter%201.xhtml".to_string()), "OEBPS/Text/chapter 1.xhtml");
+        assert_eq!(normalize_zip_path(opf, "Text/%E4%B8%AD%E6%96%87.xhtml".to_string()), "OEBPS/Text/中文.xhtml");
+        assert_eq!(normalize_zip_path(opf, "../chap.xhtml#sec2".to_string()), "chap.xhtml");
+        assert_eq!(decode_href("100%.xhtml"), "100%.xhtml");
+        assert_eq!(decode_href("a%2"), "a%2");
+        assert_eq!(decode_href("%zz%41"), "%zzA");
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("source.epub");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_copy_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("book.epub");
+        std::fs::write(&source, "epub").unwrap();
+        let mtime = FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(&source, mtime).unwrap();
+
+        let output = dir.path().join("book");
+        std::fs::create_dir_all(output.join("chapters")).unwrap();
+        std::fs::write(output.join("book.txt"), "全文").unwrap();
+        std::fs::write(output.join("metadata.toml"), "").unwrap();
+        std::fs::write(output.join("chapters/chapter_1.txt"), "第一章").unwrap();
+
+        copy_mtime(&source, &output).unwrap();
+        for file in ["book.txt", "metadata.toml", "chapters/chapter_1.txt"] {
+            let metadata = output.join(file).metadata().unwrap();
+            assert_eq!(FileTime::from_last_modification_time(&metadata), mtime);
+        }
+    }
+}